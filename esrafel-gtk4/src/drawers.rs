@@ -167,3 +167,17 @@ pub fn _draw_noise(cr: &Context, line: &Line, w: f64, h: f64, color: &Color) {
 
     cr.stroke().expect("invalid cairo surface state");
 }
+
+// Vertical bars from the bottom; x as fraction of the width, intensity from 0 to 1
+pub fn draw_sticks(cr: &Context, sticks: &[(f64, f64)], w: f64, h: f64, color: &Color) {
+    cr.set_line_width(1.0);
+    let (a, b, c) = color.as_tuple();
+    cr.set_source_rgb(a, b, c);
+
+    for (x, intensity) in sticks {
+        cr.move_to(x * w, h);
+        cr.line_to(x * w, h * (1.0 - 0.25 * intensity));
+    }
+
+    cr.stroke().expect("invalid cairo surface state");
+}
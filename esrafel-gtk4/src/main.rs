@@ -39,8 +39,13 @@ mod shortcuts;
 mod about;
 mod nuc_object;
 
-use libesrafel::Radical;
-use libesrafel::io::{Spectrum, SimulationState};
+use libesrafel::{LinePositions, Radical};
+use libesrafel::backend::{self, Optimizer, Powder, SimulationMethod, SpectrumModel};
+use libesrafel::eprft::{errore_with, Annealing, Baseline, BaselineFit, Modulation, OutputMode, SimOptions};
+use libesrafel::dynamic::ExchangeRadical;
+use libesrafel::fit::{LevMar, Uncertainty};
+use libesrafel::powder::{PowderOptions, PowderRadical, Tensor};
+use libesrafel::io::{decode_text, Spectrum, SimulationState, TextOptions, SkipReason};
 use drawers::{Line, Color};
 use params::{RadParModel, RadParMsg};
 use preferences::{PreferencesModel, PreferencesMsg};
@@ -56,13 +61,19 @@ struct ChartModel {
     background_color: Color,
     theoretical_color: Color,
     empirical_color: Color,
+    sticks_color: Color,
+    baseline_color: Color,
     theoretical_line: Option<Line>,
     empirical_line: Option<Line>,
+    baseline_line: Option<Line>,  // On the same scale of the empirical line
+    sticks: Vec<(f64, f64)>,
 }
 
 enum ChartMsg {
     AddEmpirical(Vec<f64>),
     AddTheoretical(Vec<f64>),
+    SetSticks(Vec<(f64, f64)>),
+    SetBaseline(Vec<f64>),  // Empty to hide it
     Resize((i32, i32)),
 }
 
@@ -80,8 +91,12 @@ impl ComponentUpdate<AppModel> for ChartModel {
             background_color: Color::rgb(24.0, 24.0, 22.0),
             theoretical_color: Color::rgb(230.0, 111.0, 67.0),
             empirical_color: Color::rgb(254.0, 242.0, 235.0),
+            sticks_color: Color::rgb(79.0, 134.0, 140.0),
+            baseline_color: Color::rgb(181.0, 137.0, 0.0),
             theoretical_line: None,
             empirical_line: None,
+            baseline_line: None,
+            sticks: Vec::new(),
         }
     }
 
@@ -103,6 +118,15 @@ impl ComponentUpdate<AppModel> for ChartModel {
             ChartMsg::AddTheoretical(v) => {
                 self.theoretical_line = Some(Line::new(v));
             }
+            ChartMsg::SetSticks(v) => {
+                self.sticks = v;
+            }
+            ChartMsg::SetBaseline(v) => {
+                self.baseline_line = match (&self.empirical_line, v.is_empty()) {
+                    (Some(emp), false) => Some(Line { length: v.len(), data: v, max: emp.max, min: emp.min }),
+                    _ => None,
+                };
+            }
             ChartMsg::Resize((x, y)) => {
                 self.width = x as f64;
                 self.height = y as f64;
@@ -162,6 +186,12 @@ impl Widgets<ChartModel, AppModel> for ChartWidgets {
             drawers::draw_classic(&cr, &v, model.width, model.height, &model.theoretical_color);
         };
 
+        if let Some(v) = &model.baseline_line {
+            drawers::draw_classic(&cr, &v, model.width, model.height, &model.baseline_color);
+        };
+
+        drawers::draw_sticks(&cr, &model.sticks, model.width, model.height, &model.sticks_color);
+
         // IDEA: if track!(&model.show_demo) ...
         // Draw noise or choose your opening demo
         // drawers::draw_noise(&cr, &v, model.width, model.height, &model.theoretical_color);
@@ -181,7 +211,18 @@ impl OpenDialogConfig for OpenFileButtonConfig {
 
         let filter = gtk::FileFilter::new();
         filter.add_pattern("*.txt");
+        filter.add_pattern("*.csv");
+        filter.add_pattern("*.tsv");
         filter.add_pattern("*.json");
+        // Bruker BES3T descriptor; the .DTA is read from the same folder
+        filter.add_pattern("*.DSC");
+        filter.add_pattern("*.dsc");
+        // ESP/WinEPR parameters; the .spc is read from the same folder
+        filter.add_pattern("*.PAR");
+        filter.add_pattern("*.par");
+        // JCAMP-DX
+        filter.add_pattern("*.jdx");
+        filter.add_pattern("*.dx");
 
         OpenDialogSettings {
             accept_label: "Open",
@@ -274,45 +315,283 @@ impl SaveDialogParent for AppModel {
 
 // -- AppModel
 
-// Available simulation methods
-// This will make it easier further backend extensions
-
-#[derive(Serialize, Deserialize)]
-enum SimulationMethod {
-    MC199,
-    Dynamic1999
-    // ...
+// Where the last run started: the same start with the same seed is the same run
+#[derive(Clone, Serialize, Deserialize)]
+struct RunStart {
+    method: SimulationMethod,
+    model: backend::Model,
+    sigma: f64,
+    seed: u64,
+    iters: usize,
+    sim_opts: SimOptions,
+    annealing: Annealing,
 }
 
 #[derive(Default, Serialize, Deserialize)]
 struct AppModel {
     empirical: Option<Vec<f64>>,
+    spectrum: Option<Spectrum>,  // Last loaded spectrum, with field axis and metadata
     rads: Vec<Radical>,
+    // Tensors can't be edited in the panel: they come from a JSON file,
+    // or from the panel radicals the first time the powder method runs
+    #[serde(default)]
+    powder: Vec<PowderRadical>,
+    #[serde(default)]
+    powder_opts: PowderOptions,
+    // Same for the two sites of the dynamic method
+    #[serde(default)]
+    dynamic: Vec<ExchangeRadical>,
+    #[serde(default)]
+    annealing: Annealing,
+    #[serde(default)]
+    seed: u64,  // Every run starts from it, so a saved fit can be replayed
+    #[serde(default)]
+    run: Option<RunStart>,
+    #[serde(skip)]
+    optimizer: Option<Box<dyn Optimizer>>,  // Started by the first step of a run
+    #[serde(skip)]
+    uncertainty: Option<Uncertainty>,  // At the end of the last fit
     points: i32,
     sweep: f64,
+    #[serde(default)]
+    sim_opts: SimOptions,
     sigma: f64,
     iters: usize,
     montecarlo: bool,
+    #[serde(default)]
+    show_sticks: bool,
+    #[serde(default)]
+    show_baseline: bool,
+    #[serde(skip)]
+    baseline: Option<BaselineFit>,  // Last fitted one, for the report
     log: Vec<String>,
     sim_method: Option<SimulationMethod>,
     #[serde(skip)]
     last_toast: Option<adw::Toast>,
 }
 
+impl AppModel {
+    // Every spectrum format ends up here
+    fn set_spectrum(&mut self, spectrum: Spectrum) {
+        let fld = spectrum.get_fld();
+        if let (Some(first), Some(last)) = (fld.first(), fld.last()) {
+            self.sweep = (last - first).abs();
+            self.points = fld.len() as i32;
+        }
+
+        // Keep the frequency we had if the file doesn't know it
+        if let Some(mwfq) = spectrum.get_meta().mwfq {
+            self.sim_opts.mwfq = Some(mwfq);
+        }
+        if let Some(amplitude) = spectrum.get_meta().mod_amp {
+            let harmonic = self.sim_opts.modulation.as_ref().map_or(1, |m| m.harmonic);
+            self.sim_opts.modulation = Some(Modulation { amplitude, harmonic });
+        }
+
+        self.empirical = Some(spectrum.get_int());
+        self.spectrum = Some(spectrum);
+        self.optimizer = None;
+    }
+
+    // Experimental field axis if we have a spectrum,
+    // otherwise a uniform one built from sweep and points,
+    // centered on the free electron resonance when the frequency is known.
+    fn field_axis(&self) -> Vec<f64> {
+        match &self.spectrum {
+            Some(spectrum) => spectrum.get_fld(),
+            None => {
+                let step = self.sweep / (self.points - 1).max(1) as f64;
+                let start = match self.sim_opts.mwfq {
+                    Some(mwfq) => libesrafel::eprft::resonance_field(libesrafel::G_E, mwfq) - self.sweep / 2.0,
+                    None => 0.0,
+                };
+                (0..self.points).map(|i| start + i as f64 * step).collect()
+            }
+        }
+    }
+
+    // Lines of every radical, as (x fraction of the plot, relative intensity)
+    fn stick_overlay(&self) -> Vec<(f64, f64)> {
+        let fld = self.field_axis();
+        let (first, last) = match (fld.first(), fld.last()) {
+            (Some(first), Some(last)) if first != last => (*first, *last),
+            _ => return Vec::new(),
+        };
+
+        let center = (first + last) / 2.0;
+        let mut sticks = Vec::new();
+        for rad in &self.rads {
+            let b0 = match self.sim_opts.mwfq {
+                Some(mwfq) => libesrafel::eprft::resonance_field(rad.g.val, mwfq),
+                None => center,
+            } + rad.dh1.val;
+
+            let lines = libesrafel::stick_spectrum_at(rad, b0, self.sim_opts.positions);
+            let totale: f64 = lines.iter().map(|l| l.intensity).sum();
+            sticks.extend(lines.iter().map(|l| {
+                ((l.field - first) / (last - first), rad.amount.val * l.intensity / totale)
+            }));
+        }
+
+        let max = sticks.iter().map(|(_, int)| *int).fold(0.0, f64::max);
+        if max > 0.0 {
+            sticks.iter_mut().for_each(|(_, int)| *int /= max);
+        }
+        sticks
+    }
+
+    // Coefficients of the last fitted baseline, lowest order first
+    fn baseline_report(&self) -> String {
+        match &self.baseline {
+            Some(fit) => {
+                let coeffs: Vec<String> = fit.coeffs.iter().map(|c| format!("{:.4e}", c)).collect();
+                format!("Baseline (Legendre): {}", coeffs.join(", "))
+            }
+            None => String::new(),
+        }
+    }
+
+    fn is_powder(&self) -> bool {
+        matches!(self.sim_method, Some(SimulationMethod::Powder))
+    }
+
+    fn powder_rads(&mut self) -> Vec<PowderRadical> {
+        if self.powder.is_empty() {
+            self.powder = self.rads.iter().map(PowderRadical::from).collect();
+        }
+        self.powder.clone()
+    }
+
+    // Error bars at the current radicals; only the panel ones, against a spectrum
+    fn estimate_uncertainties(&mut self) {
+        self.uncertainty = match &self.empirical {
+            Some(emp) if self.method().is_isotropic() => {
+                libesrafel::fit::uncertainties(emp, &self.field_axis(), &self.sim_opts, &self.rads)
+            }
+            _ => None,
+        };
+    }
+
+    // One varied parameter per line, then the pairs that move together
+    fn uncertainty_report(&self) -> String {
+        match &self.uncertainty {
+            Some(unc) => {
+                let mut lines: Vec<String> = unc.names.iter().zip(unc.values.iter()).zip(unc.errors.iter())
+                    .map(|((name, val), err)| {
+                        if err.is_finite() {
+                            format!("{}: {:.6} ± {:.2e}", name, val, err)
+                        } else {
+                            format!("{}: {:.6} (not determinable)", name, val)
+                        }
+                    })
+                    .collect();
+                for (i, j, r) in unc.strongly_correlated() {
+                    lines.push(format!("⚠ {} and {} are correlated: {:.3}", unc.names[i], unc.names[j], r));
+                }
+                lines.join("\n")
+            }
+            None => String::new(),
+        }
+    }
+
+    // Principal values of the powder tensors, as fitted: the panel only edits isotropic radicals
+    fn powder_report(&self) -> String {
+        let tensor = |t: &Tensor| {
+            let [x, y, z] = &t.principal;
+            if t.euler == [0.0; 3] {
+                format!("{:.5} {:.5} {:.5}", x.val, y.val, z.val)
+            } else {
+                format!("{:.5} {:.5} {:.5} (Euler {:.1}° {:.1}° {:.1}°)", x.val, y.val, z.val, t.euler[0], t.euler[1], t.euler[2])
+            }
+        };
+
+        let mut lines = Vec::new();
+        for (r, rad) in self.powder.iter().enumerate() {
+            lines.push(format!("Radical {}: g {}, lw {:.3}", r + 1, tensor(&rad.g), rad.lwa.val));
+            for (n, nuc) in rad.nucs.iter().enumerate() {
+                lines.push(format!("  A{} [G]: {}", n + 1, tensor(&nuc.a)));
+            }
+        }
+        lines.join("\n")
+    }
+
+    fn is_annealing(&self) -> bool {
+        matches!(self.sim_method, Some(SimulationMethod::Annealing))
+    }
+
+    // Nelder–Mead doesn't draw random moves
+    fn is_stochastic(&self) -> bool {
+        !matches!(self.sim_method, Some(SimulationMethod::NelderMead))
+    }
+
+    fn method(&self) -> SimulationMethod {
+        self.sim_method.unwrap_or_default()
+    }
+
+    // Radicals the current method works on; relm4 has its own Model
+    fn model(&mut self) -> backend::Model {
+        match self.method() {
+            SimulationMethod::Powder => backend::Model::Powder(Powder { rads: self.powder_rads(), opts: self.powder_opts.clone() }),
+            SimulationMethod::Dynamic1999 => backend::Model::Dynamic(self.dynamic_rads()),
+            _ => backend::Model::Isotropic(self.rads.clone()),
+        }
+    }
+
+    fn store(&mut self, model: backend::Model) {
+        match model {
+            backend::Model::Isotropic(rads) => self.rads = rads,
+            backend::Model::Dynamic(rads) => self.dynamic = rads,
+            backend::Model::Powder(powder) => self.powder = powder.rads,
+        }
+    }
+
+    // A paused run goes on only with the settings it started with
+    fn same_run(&self) -> bool {
+        self.run.as_ref().is_some_and(|run| {
+            run.method == self.method() && run.seed == self.seed
+                && run.sim_opts == self.sim_opts && run.annealing == self.annealing
+        })
+    }
+
+    fn dynamic_rads(&mut self) -> Vec<ExchangeRadical> {
+        if self.dynamic.is_empty() {
+            self.dynamic = self.rads.iter().map(ExchangeRadical::from).collect();
+        }
+        self.dynamic.clone()
+    }
+}
+
 enum AppMsg {
     IterMontecarlo,
     Redraw,
     ToggleMontecarlo(bool),
+    Replay,
     Open(PathBuf),
+    OpenText(String, TextOptions),
     UpdateRads(Vec<Radical>),
     SetSweep(f64),
     SetPoints(i32),  // then, temporarily convert to f64
+    SetMwfq(f64),  // 0 means unknown
+    SetModAmp(f64),  // 0 means no overmodulation
+    SetHarmonic(u32),
+    SetLinePositions(LinePositions),
+    SetOutputMode(OutputMode),
+    SetPowderKnots(usize),
+    SetTemperature(f64),
+    SetCooling(f64),
+    SetReheats(usize),
+    SetSeed(u64),
+    Refine,
+    ToggleSticks(bool),
+    SetBaseline(Baseline),
+    ToggleBaseline(bool),
     ClearPanel,
     RefreshPanel,
     SpawnToast(String),
     ResetToast,
     SetSimMethod(SimulationMethod),
     SaveRequest,
+    ExportRequest,
     SaveResponse(PathBuf),
     ShowPreferences,
     ShowAbout,
@@ -343,6 +622,7 @@ impl AppUpdate for AppModel {
         match msg {
             AppMsg::UpdateRads(new_rads) => {
                 self.rads = new_rads;
+                self.optimizer = None;
                 let action_string = format!("Updated! You are working with {} radicals now.", self.rads.len());
                 send!(sender, AppMsg::SpawnToast(action_string));
             }
@@ -357,42 +637,143 @@ impl AppUpdate for AppModel {
                 // This is a fast and working solution, but a persistent iteration is not an elegant move
                 // Must search for another tracking method, but it's not a priority rn
                 if self.montecarlo {
-                    if let Some(emp) = &self.empirical {
+                    if let Some(emp) = self.empirical.clone() {
+                        let fld = self.field_axis();
+                        let mut optimizer = match self.optimizer.take() {
+                            Some(optimizer) => optimizer,
+                            None => {
+                                // A new run: sigma of its start, not of whatever was shown before
+                                let model = self.model();
+                                let (sigma, _) = errore_with(&emp, model.simulate(&fld, &self.sim_opts), self.sim_opts.baseline);
+                                self.sigma = sigma;
+                                self.run = Some(RunStart {
+                                    method: self.method(),
+                                    model: model.clone(),
+                                    sigma,
+                                    seed: self.seed,
+                                    iters: self.iters,
+                                    sim_opts: self.sim_opts.clone(),
+                                    annealing: self.annealing.clone(),
+                                });
+                                self.method().optimizer(model, sigma, &self.annealing, self.seed, &emp, &fld, &self.sim_opts)
+                            }
+                        };
 
-                        let (newsigma, newteor, newrads) =
-                            libesrafel::eprft::mc_fit(
-                                &emp,
-                                self.points as f64,
-                                self.sweep,
-                                self.sigma,
-                                self.rads.clone(),
-                            );
+                        let was_converged = optimizer.converged();
+                        let newteor = optimizer.step(&emp, &fld, &self.sim_opts);
+                        if optimizer.converged() && !was_converged {
+                            send!(sender, AppMsg::SpawnToast(format!("Converged after {} iterations", self.iters)));
+                        }
 
-                        self.sigma = newsigma;
-                        self.rads = newrads;
+                        // The panel shows the best so far, whatever the walk is doing
+                        self.sigma = optimizer.best_sigma();
+                        self.store(optimizer.best());
+                        self.optimizer = Some(optimizer);
 
                         components.chart.send(ChartMsg::AddTheoretical(newteor))
                                         .expect("Failed sending new theoretical spectrum to the Chart");
 
-                        // Randomize parameters for next iteration
-                        // self.newrads = sim::caso(&self.rads);
-
                         self.iters+=1;
                     } // if empirical exists
                 } // if montecarlo toggled
             }
             AppMsg::Redraw => {
-                // TODO it shouldn't call directly the simulator function
-                // Must be compatible with every future simulator implementation
                 if self.montecarlo {
-                    components.chart.send(ChartMsg::AddTheoretical(
-                        libesrafel::eprft::calcola(&self.rads, self.sweep, self.points as f64)))
+                    let mut teor = self.model().simulate(&self.field_axis(), &self.sim_opts);
+
+                    // Same baseline the fit is using, so the curves can be compared
+                    self.baseline = match (&self.empirical, self.sim_opts.baseline) {
+                        (Some(emp), Baseline::Polynomial(order)) => libesrafel::eprft::fit_baseline(emp, &teor, order),
+                        _ => None,
+                    };
+                    if let Some(fit) = &self.baseline {
+                        teor = teor.iter().zip(fit.values.iter()).map(|(y, b)| fit.norma * y + b).collect();
+                    }
+
+                    components.chart.send(ChartMsg::AddTheoretical(teor))
                                     .expect("Failed sending new theoretical spectrum to the Chart");
                 }
+
+                let baseline = match &self.baseline {
+                    Some(fit) if self.show_baseline => fit.values.clone(),
+                    _ => Vec::new(),
+                };
+                components.chart.send(ChartMsg::SetBaseline(baseline))
+                                .expect("Failed sending the baseline to the Chart");
+
+                let sticks = if self.show_sticks && self.method().is_isotropic() { self.stick_overlay() } else { Vec::new() };
+                components.chart.send(ChartMsg::SetSticks(sticks))
+                                .expect("Failed sending sticks to the Chart");
+            }
+            AppMsg::Refine => {
+                // Levenberg–Marquardt from where the Monte Carlo stopped; radicals of the panel only
+                if !self.method().is_isotropic() {
+                    send!(sender, AppMsg::SpawnToast("Refinement works on the radicals of the panel only".into()));
+                } else if let Some(emp) = self.empirical.clone() {
+                    let fld = self.field_axis();
+                    let refined = libesrafel::fit::levenberg_marquardt(
+                        &emp,
+                        &fld,
+                        &self.sim_opts,
+                        self.rads.clone(),
+                        &LevMar::default(),
+                    );
+
+                    // Refined on the smooth spectrum; sigma of the one the walk sees, to compare
+                    let (sigma, teor) = errore_with(&emp, refined.rads.simulate(&fld, &self.sim_opts), self.sim_opts.baseline);
+                    let msg = if refined.converged {
+                        format!("Refined in {} iterations, sigma = {:.4e}", refined.iters, sigma)
+                    } else {
+                        format!("Not converged after {} iterations, sigma = {:.4e}", refined.iters, sigma)
+                    };
+
+                    // It's still the best: a walk can go on from here
+                    self.sigma = sigma;
+                    self.rads = refined.rads;
+                    self.optimizer = None;
+                    self.estimate_uncertainties();
+
+                    components.chart.send(ChartMsg::AddTheoretical(teor))
+                                    .expect("Failed sending new theoretical spectrum to the Chart");
+                    send!(sender, AppMsg::RefreshPanel);
+                    send!(sender, AppMsg::SpawnToast(msg));
+                } else {
+                    send!(sender, AppMsg::SpawnToast("Open a spectrum to refine against".into()));
+                }
             }
             AppMsg::ToggleMontecarlo(is_going) => {
                 self.montecarlo = is_going;
+                // Resuming keeps the walk the seed gives; new settings start a new run,
+                // which anneals from the initial temperature, or spans a new simplex
+                if is_going {
+                    if !self.same_run() {
+                        self.optimizer = None;
+                    }
+                } else {
+                    self.estimate_uncertainties();
+                }
             }  // ./Montecarlo
+            AppMsg::Replay => {
+                // Back to the start of the last run: running again walks the same way
+                if let Some(run) = self.run.clone() {
+                    self.sim_method = Some(run.method);
+                    if let backend::Model::Powder(powder) = &run.model {
+                        self.powder_opts = powder.opts.clone();
+                    }
+                    self.store(run.model);
+                    self.sigma = run.sigma;
+                    self.seed = run.seed;
+                    self.iters = run.iters;
+                    self.sim_opts = run.sim_opts;
+                    self.annealing = run.annealing;
+                    self.optimizer = None;
+                    self.uncertainty = None;
+                    send!(sender, AppMsg::RefreshPanel);
+                    send!(sender, AppMsg::SpawnToast(format!("Back to the start of the run with seed {}", self.seed)));
+                } else {
+                    send!(sender, AppMsg::SpawnToast("No run to replay yet".into()));
+                }
+            }
             AppMsg::Open(path) => {
                 let mut data = String::new();
 
@@ -402,41 +783,130 @@ impl AppUpdate for AppModel {
 
                     match ext_as_str {
                         "txt" => {
-                            // TODO nest this block into the next one
-                            let f: Option<File>;
-
-                            match File::open(path) {
-                                Ok(file) => f = Some(file),
+                            match std::fs::read_to_string(&path) {
+                                Ok(content) => {
+                                    // Old exports have index, field and intensity; the rest, field and intensity
+                                    let last = content.lines().rev().find(|l| !l.trim().is_empty()).unwrap_or("");
+                                    let opts = if last.split_whitespace().count() == 3 {
+                                        TextOptions::three_columns()
+                                    } else {
+                                        TextOptions::default()
+                                    };
+                                    send!(sender, AppMsg::OpenText(content, opts));
+                                }
                                 Err(e) => {
                                     let err_string = format!("Unable to open txt file. Error: {}", e);
                                     send!(sender, AppMsg::SpawnToast(err_string));
-                                    f = None;
                                 }
-                            };
-
-                            match f {
-                                Some(mut file) => {
-                                    match file.read_to_string(&mut data) {
-                                        Ok(_) => {
-                                            self.empirical = Some(Spectrum::from_ascii(&data).get_int());
+                            }
+                        } // txt case
+                        "DSC" | "dsc" => {
+                            // Xepr writes the data next to the descriptor, same name
+                            let dta_ext = if ext_as_str == "DSC" { "DTA" } else { "dta" };
+
+                            match (std::fs::read(&path), std::fs::read(path.with_extension(dta_ext))) {
+                                (Ok(dsc), Ok(dta)) => {
+                                    match Spectrum::from_bes3t(&decode_text(&dsc), &dta) {
+                                        Ok(spectrum) => {
+                                            self.set_spectrum(spectrum);
                                             send!(sender, AppMsg::SpawnToast("Loaded!".into()));
-                                        },
+                                        }
                                         Err(e) => {
-                                            let err_string = format!("Unable to read string in this file. Error: {}", e);
+                                            let err_string = format!("Unable to read BES3T spectrum. Error: {}", e);
                                             send!(sender, AppMsg::SpawnToast(err_string));
                                         }
                                     }
                                 }
-                                None => {
-                                    // Do nothing
+                                (Err(e), _) | (_, Err(e)) => {
+                                    let err_string = format!("Unable to open BES3T files. Error: {}", e);
+                                    send!(sender, AppMsg::SpawnToast(err_string));
                                 }
                             }
-                        } // txt case
+                        }  // BES3T case
+                        "PAR" | "par" => {
+                            let spc_ext = if ext_as_str == "PAR" { "SPC" } else { "spc" };
+                            match (std::fs::read(&path), std::fs::read(path.with_extension(spc_ext))) {
+                                (Ok(par), Ok(spc)) => {
+                                    match Spectrum::from_winepr(&decode_text(&par), &spc) {
+                                        Ok(spectrum) => {
+                                            self.set_spectrum(spectrum);
+                                            send!(sender, AppMsg::SpawnToast("Loaded!".into()));
+                                        }
+                                        Err(e) => {
+                                            let err_string = format!("Unable to read WinEPR spectrum. Error: {}", e);
+                                            send!(sender, AppMsg::SpawnToast(err_string));
+                                        }
+                                    }
+                                }
+                                (Err(e), _) | (_, Err(e)) => {
+                                    let err_string = format!("Unable to open WinEPR files. Error: {}", e);
+                                    send!(sender, AppMsg::SpawnToast(err_string));
+                                }
+                            }
+                        }  // WinEPR case
+                        "jdx" | "dx" | "jcamp" => {
+                            match std::fs::read_to_string(&path) {
+                                Ok(content) => {
+                                    match Spectrum::from_jcampdx(&content) {
+                                        Ok(spectrum) => {
+                                            self.set_spectrum(spectrum);
+                                            send!(sender, AppMsg::SpawnToast("Loaded!".into()));
+                                        }
+                                        Err(e) => {
+                                            let err_string = format!("Unable to read JCAMP-DX spectrum. Error: {}", e);
+                                            send!(sender, AppMsg::SpawnToast(err_string));
+                                        }
+                                    }
+                                }
+                                Err(e) => {
+                                    let err_string = format!("Unable to open JCAMP-DX file. Error: {}", e);
+                                    send!(sender, AppMsg::SpawnToast(err_string));
+                                }
+                            }
+                        }  // JCAMP-DX case
+                        "csv" | "tsv" => {
+                            let opts = if ext_as_str == "csv" { TextOptions::csv() } else { TextOptions::tsv() };
+                            match std::fs::read_to_string(&path) {
+                                Ok(content) => send!(sender, AppMsg::OpenText(content, opts)),
+                                Err(e) => {
+                                    let err_string = format!("Unable to open text file. Error: {}", e);
+                                    send!(sender, AppMsg::SpawnToast(err_string));
+                                }
+                            }
+                        }
                         "esr" => {
                             send!(sender, AppMsg::SpawnToast("Legacy format not supported yet!".into()));
                         }
                         "json" => {
-                            send!(sender, AppMsg::SpawnToast("JSON format not supported yet!".into()));
+                            // Powder radicals, as a JSON list of `PowderRadical`,
+                            // or exchanging ones, as a list of `ExchangeRadical`
+                            match std::fs::read_to_string(&path) {
+                                Ok(content) => {
+                                    if let Ok(powder) = serde_json::from_str::<Vec<PowderRadical>>(&content) {
+                                        let msg = format!("Loaded {} powder radicals!", powder.len());
+                                        self.powder = powder;
+                                        self.sim_method = Some(SimulationMethod::Powder);
+                                        send!(sender, AppMsg::SpawnToast(msg));
+                                    } else {
+                                        match serde_json::from_str::<Vec<ExchangeRadical>>(&content) {
+                                            Ok(dynamic) => {
+                                                let msg = format!("Loaded {} exchanging radicals!", dynamic.len());
+                                                self.dynamic = dynamic;
+                                                self.sim_method = Some(SimulationMethod::Dynamic1999);
+                                                send!(sender, AppMsg::SpawnToast(msg));
+                                            }
+                                            Err(e) => {
+                                                let err_string = format!("Unable to read radicals. Error: {}", e);
+                                                send!(sender, AppMsg::SpawnToast(err_string));
+                                            }
+                                        }
+                                    }
+                                }
+                                Err(e) => {
+                                    let err_string = format!("Unable to read radicals. Error: {}", e);
+                                    send!(sender, AppMsg::SpawnToast(err_string));
+                                }
+                            }
                         }  // json case
                         "esrafel" => {
                             // Show this if loading succeeds
@@ -485,12 +955,26 @@ impl AppUpdate for AppModel {
                                 Ok(mut file) => {
                                     match file.read_to_string(&mut data) {
                                         Ok(_) => {
-                                            let (points, sweep, rads) =
-                                                SimulationState::from_simfile(&data).into_tuple();
+                                            match SimulationState::from_simfile(&data) {
+                                                Ok(state) => {
+                                                    if let Some(mwfq) = state.get_mwfq() {
+                                                        self.sim_opts.mwfq = Some(mwfq);
+                                                    }
+                                                    let (points, sweep, rads) = state.into_tuple();
+
+                                                    self.points = points;
+                                                    self.sweep = sweep.into();
+                                                    self.rads = rads;
 
-                                            self.points = points;
-                                            self.sweep = sweep.into();
-                                            self.rads = rads;
+                                                    send!(sender, AppMsg::SpawnToast(success_string));
+                                                    send!(sender, AppMsg::ClearPanel);
+                                                    send!(sender, AppMsg::RefreshPanel);
+                                                }
+                                                Err(e) => {
+                                                    let err_string = format!("Unable to read SIM file. Error: {}", e);
+                                                    send!(sender, AppMsg::SpawnToast(err_string));
+                                                }
+                                            }
                                         }
                                         Err(e) => {
                                             let err_string = format!("Unable to load this state. Error: {}", e);
@@ -498,9 +982,6 @@ impl AppUpdate for AppModel {
                                         }
 
                                     }
-                                    send!(sender, AppMsg::SpawnToast(success_string));
-                                    send!(sender, AppMsg::ClearPanel);
-                                    send!(sender, AppMsg::RefreshPanel);
                                 }
                                 Err(e) => {
                                     let err_string = format!("Unable to read string in this file. Error: {}", e);
@@ -520,12 +1001,79 @@ impl AppUpdate for AppModel {
                                     .expect("Failed sending empirical spectrum to the Chart");
                 }
             }
+            AppMsg::OpenText(content, opts) => {
+                match Spectrum::from_text(&content, &opts) {
+                    Ok((spectrum, report)) => {
+                        self.set_spectrum(spectrum);
+                        let msg = match report.skipped.iter().find(|s| s.reason != SkipReason::Header) {
+                            Some(first) => format!(
+                                "Loaded {} rows, skipped {} (line {}: {})",
+                                report.rows, report.skipped.len(), first.line, first.reason
+                            ),
+                            None => "Loaded!".into(),
+                        };
+                        send!(sender, AppMsg::SpawnToast(msg));
+                    }
+                    Err(e) => {
+                        let err_string = format!("Unable to read spectrum. Error: {}", e);
+                        send!(sender, AppMsg::SpawnToast(err_string));
+                    }
+                }
+
+                if let Some(emp) = &self.empirical {
+                    components.chart.send(ChartMsg::AddEmpirical(emp.to_vec()))
+                                    .expect("Failed sending empirical spectrum to the Chart");
+                }
+            }
             AppMsg::SetSweep(value) => {
                 self.sweep = value;
             }
             AppMsg::SetPoints(value) => {
                 self.points = value;
             }
+            AppMsg::SetMwfq(value) => {
+                self.sim_opts.mwfq = if value > 0.0 { Some(value) } else { None };
+            }
+            AppMsg::SetModAmp(value) => {
+                let harmonic = self.sim_opts.modulation.as_ref().map_or(1, |m| m.harmonic);
+                self.sim_opts.modulation = if value > 0.0 { Some(Modulation { amplitude: value, harmonic }) } else { None };
+            }
+            AppMsg::SetHarmonic(harmonic) => {
+                let amplitude = self.sim_opts.modulation.as_ref().map_or(0.0, |m| m.amplitude);
+                self.sim_opts.modulation = Some(Modulation { amplitude, harmonic });
+            }
+            AppMsg::SetLinePositions(positions) => {
+                self.sim_opts.positions = positions;
+            }
+            AppMsg::SetOutputMode(output) => {
+                self.sim_opts.output = output;
+            }
+            AppMsg::SetPowderKnots(knots) => {
+                self.powder_opts.knots = knots;
+                self.optimizer = None;
+            }
+            AppMsg::SetTemperature(temperature) => {
+                self.annealing.temperature = temperature;
+            }
+            AppMsg::SetCooling(cooling) => {
+                self.annealing.cooling = cooling;
+            }
+            AppMsg::SetReheats(reheats) => {
+                self.annealing.reheats = reheats;
+            }
+            AppMsg::SetSeed(seed) => {
+                self.seed = seed;
+            }
+            AppMsg::ToggleSticks(show) => {
+                self.show_sticks = show;
+            }
+            AppMsg::SetBaseline(baseline) => {
+                self.sim_opts.baseline = baseline;
+                self.baseline = None;
+            }
+            AppMsg::ToggleBaseline(show) => {
+                self.show_baseline = show;
+            }
             AppMsg::SpawnToast(msg) => {
                 self.last_toast = Some(adw::Toast::new(&msg));
                 self.log.push(msg);
@@ -535,6 +1083,7 @@ impl AppUpdate for AppModel {
             }
             AppMsg::SetSimMethod(method) => {
                 self.sim_method = Some(method);
+                self.optimizer = None;
             }
             AppMsg::SaveRequest => {
                 components
@@ -542,6 +1091,33 @@ impl AppUpdate for AppModel {
                     .send(SaveDialogMsg::SaveAs(".esrafel".into()))
                     .unwrap();
             }
+            AppMsg::ExportRequest => {
+                components
+                    .save_dialog
+                    .send(SaveDialogMsg::SaveAs("spectrum.jdx".into()))
+                    .unwrap();
+            }
+            AppMsg::SaveResponse(path) if matches!(path.extension().and_then(|e| e.to_str()), Some("jdx" | "dx")) => {
+                // Export the loaded spectrum as JCAMP-DX
+                match &self.spectrum {
+                    Some(spectrum) => {
+                        match std::fs::write(&path, spectrum.into_jcampdx()) {
+                            Ok(_) => {
+                                send!(sender, AppMsg::SpawnToast(
+                                    format!("Spectrum successfully exported into {:?}", &path)
+                                ));
+                            }
+                            Err(e) => {
+                                let err_string = format!("Unable to create file. Error: {}", e);
+                                send!(sender, AppMsg::SpawnToast(err_string));
+                            }
+                        }
+                    }
+                    None => {
+                        send!(sender, AppMsg::SpawnToast("Open a spectrum before exporting it!".into()));
+                    }
+                }
+            }
             AppMsg::SaveResponse(path) => {
                 // Serialize model
                 match serde_json::to_string(&self) {
@@ -660,6 +1236,12 @@ impl Widgets<AppModel, ()> for AppWidgets {
                                                             send!(sender, AppMsg::SaveRequest);
                                                         },
                                                     },
+                                                    append = &gtk::Button {
+                                                        set_label: "Export Spectrum",
+                                                        connect_clicked(sender) => move |_| {
+                                                            send!(sender, AppMsg::ExportRequest);
+                                                        },
+                                                    },
                                                 },
                                             },
                                             append: sweep_entry = &gtk::Box {
@@ -683,6 +1265,8 @@ impl Widgets<AppModel, ()> for AppWidgets {
                                                         100.0,  // page_increment
                                                         1000.0  // page_size
                                                     ),
+                                                    // Filled in from the data when a spectrum is opened
+                                                    set_value: watch!(model.sweep),
                                                     connect_value_changed(sender) => move |val| {
                                                         send!(sender, AppMsg::SetSweep(val.value()))
                                                     }
@@ -707,11 +1291,127 @@ impl Widgets<AppModel, ()> for AppWidgets {
                                                         10.0,  // page_increment
                                                         1000.0  // page_size
                                                     ),
+                                                    set_value: watch!(model.points as f64),
                                                     connect_value_changed(sender) => move |val| {
                                                         send!(sender, AppMsg::SetPoints(val.value_as_int() as i32));
                                                     }
                                                 },
                                             },
+                                            append: mwfq_entry = &gtk::Box {
+                                                set_orientation: gtk::Orientation::Horizontal,
+                                                set_spacing: 5,
+                                                set_margin_top: 5,
+                                                set_margin_bottom: 5,
+                                                set_margin_start: 5,
+                                                set_margin_end: 5,
+                                                append: &gtk::Label::new(Some("Frequency (GHz)")),
+                                                append: mwfq_spin = &gtk::SpinButton {
+                                                    set_width_chars: 5,
+                                                    set_digits: 4,
+                                                    set_tooltip_text: Some("Leave at 0 to center radicals on the sweep"),
+                                                    set_adjustment: &gtk::Adjustment::new(
+                                                        model.sim_opts.mwfq.unwrap_or(0.0),  // value
+                                                        0.0,  // lower
+                                                        1000.0,  // upper
+                                                        0.01,  // step_increment
+                                                        0.1,  // page_increment
+                                                        1.0  // page_size
+                                                    ),
+                                                    // Filled in from the data when a spectrum is opened
+                                                    set_value: watch!(model.sim_opts.mwfq.unwrap_or(0.0)),
+                                                    connect_value_changed(sender) => move |val| {
+                                                        send!(sender, AppMsg::SetMwfq(val.value()));
+                                                    }
+                                                },
+                                            },
+                                            append = &gtk::Box {
+                                                set_orientation: gtk::Orientation::Horizontal,
+                                                set_spacing: 5,
+                                                set_margin_start: 5,
+                                                set_margin_end: 5,
+                                                set_margin_top: 5,
+                                                set_margin_bottom: 5,
+                                                append: &gtk::Label::new(Some("Modulation (G)")),
+                                                append: mod_amp_spin = &gtk::SpinButton {
+                                                    set_width_chars: 5,
+                                                    set_digits: 3,
+                                                    set_tooltip_text: Some("Peak to peak amplitude; leave at 0 to ignore overmodulation"),
+                                                    set_adjustment: &gtk::Adjustment::new(
+                                                        0.0,  // value
+                                                        0.0,  // lower
+                                                        1000.0,  // upper
+                                                        0.01,  // step_increment
+                                                        0.1,  // page_increment
+                                                        1.0  // page_size
+                                                    ),
+                                                    // Filled in from the data when a spectrum is opened
+                                                    set_value: watch!(model.sim_opts.modulation.as_ref().map_or(0.0, |m| m.amplitude)),
+                                                    connect_value_changed(sender) => move |val| {
+                                                        send!(sender, AppMsg::SetModAmp(val.value()));
+                                                    }
+                                                },
+                                                append: &gtk::Label::new(Some("Harmonic")),
+                                                append: harmonic_spin = &gtk::SpinButton {
+                                                    set_width_chars: 2,
+                                                    set_digits: 0,
+                                                    set_adjustment: &gtk::Adjustment::new(
+                                                        1.0,  // value
+                                                        0.0,  // lower
+                                                        4.0,  // upper
+                                                        1.0,  // step_increment
+                                                        1.0,  // page_increment
+                                                        0.0  // page_size
+                                                    ),
+                                                    set_value: watch!(model.sim_opts.modulation.as_ref().map_or(1, |m| m.harmonic) as f64),
+                                                    connect_value_changed(sender) => move |val| {
+                                                        send!(sender, AppMsg::SetHarmonic(val.value() as u32));
+                                                    }
+                                                },
+                                            },
+                                            append = &gtk::Box {
+                                                set_orientation: gtk::Orientation::Horizontal,
+                                                set_spacing: 5,
+                                                set_margin_start: 5,
+                                                set_margin_end: 5,
+                                                set_margin_top: 5,
+                                                set_margin_bottom: 5,
+                                                append: &gtk::Label::new(Some("Line positions")),
+                                                append: positions_entry = &gtk::ComboBoxText {
+                                                    append_text: "First order",
+                                                    append_text: "Second order",
+                                                    append_text: "Breit-Rabi",
+                                                    connect_changed(sender) => move |selector| {
+                                                        match selector.active_text().expect("cannot get combobox value").as_str() {
+                                                            "First order" => send!(sender, AppMsg::SetLinePositions(LinePositions::FirstOrder)),
+                                                            "Second order" => send!(sender, AppMsg::SetLinePositions(LinePositions::SecondOrder)),
+                                                            "Breit-Rabi" => send!(sender, AppMsg::SetLinePositions(LinePositions::BreitRabi)),
+                                                            _ => send!(sender, AppMsg::SpawnToast("Invalid ComboBox Value for Line positions".into())),
+                                                        }
+                                                    }
+                                                },
+                                            },
+                                            append = &gtk::Box {
+                                                set_orientation: gtk::Orientation::Horizontal,
+                                                set_spacing: 5,
+                                                set_margin_start: 5,
+                                                set_margin_end: 5,
+                                                set_margin_top: 5,
+                                                set_margin_bottom: 5,
+                                                append: &gtk::Label::new(Some("Output")),
+                                                append: output_entry = &gtk::ComboBoxText {
+                                                    append_text: "Absorption",
+                                                    append_text: "1st derivative",
+                                                    append_text: "2nd derivative",
+                                                    connect_changed(sender) => move |selector| {
+                                                        match selector.active_text().expect("cannot get combobox value").as_str() {
+                                                            "Absorption" => send!(sender, AppMsg::SetOutputMode(OutputMode::Absorption)),
+                                                            "1st derivative" => send!(sender, AppMsg::SetOutputMode(OutputMode::FirstDerivative)),
+                                                            "2nd derivative" => send!(sender, AppMsg::SetOutputMode(OutputMode::SecondDerivative)),
+                                                            _ => send!(sender, AppMsg::SpawnToast("Invalid ComboBox Value for Output".into())),
+                                                        }
+                                                    }
+                                                },
+                                            },
                                             append = &gtk::Box {
                                                 set_orientation: gtk::Orientation::Horizontal,
                                                 set_spacing: 5,
@@ -723,10 +1423,16 @@ impl Widgets<AppModel, ()> for AppWidgets {
                                                 append: sim_method_entry = &gtk::ComboBoxText {
                                                     append_text: "MC 1999",
                                                     append_text: "Dynamic 1999",
+                                                    append_text: "Powder",
+                                                    append_text: "Annealing",
+                                                    append_text: "Nelder-Mead",
                                                     connect_changed(sender) => move |selector| {
                                                         match selector.active_text().expect("cannot get combobox value").as_str() {
                                                             "MC 1999" => send!(sender, AppMsg::SetSimMethod(SimulationMethod::MC199)),
                                                             "Dynamic 1999" => send!(sender, AppMsg::SetSimMethod(SimulationMethod::Dynamic1999)),
+                                                            "Powder" => send!(sender, AppMsg::SetSimMethod(SimulationMethod::Powder)),
+                                                            "Annealing" => send!(sender, AppMsg::SetSimMethod(SimulationMethod::Annealing)),
+                                                            "Nelder-Mead" => send!(sender, AppMsg::SetSimMethod(SimulationMethod::NelderMead)),
                                                             _ => send!(sender, AppMsg::SpawnToast("Invalid ComboBox Value for Sim. Method".into())),
 
                                                         }
@@ -734,6 +1440,181 @@ impl Widgets<AppModel, ()> for AppWidgets {
                                                 },  // toggle button
 
                                             },
+                                            append = &gtk::CheckButton {
+                                                set_label: Some("Show sticks"),
+                                                set_margin_start: 5,
+                                                set_margin_end: 5,
+                                                set_active: model.show_sticks,
+                                                connect_toggled(sender) => move |check| {
+                                                    send!(sender, AppMsg::ToggleSticks(check.is_active()));
+                                                }
+                                            },
+                                            append = &gtk::Box {
+                                                set_orientation: gtk::Orientation::Horizontal,
+                                                set_spacing: 5,
+                                                set_margin_start: 5,
+                                                set_margin_end: 5,
+                                                set_margin_top: 5,
+                                                set_margin_bottom: 5,
+                                                append: &gtk::Label::new(Some("Baseline")),
+                                                append: baseline_entry = &gtk::ComboBoxText {
+                                                    append_text: "None",
+                                                    append_text: "Offset",
+                                                    append_text: "Linear",
+                                                    append_text: "Quadratic",
+                                                    append_text: "Cubic",
+                                                    connect_changed(sender) => move |selector| {
+                                                        let baseline = match selector.active() {
+                                                            Some(order) if order > 0 => Baseline::Polynomial(order as usize - 1),
+                                                            _ => Baseline::None,
+                                                        };
+                                                        send!(sender, AppMsg::SetBaseline(baseline));
+                                                    }
+                                                },
+                                                append = &gtk::CheckButton {
+                                                    set_label: Some("Show"),
+                                                    set_active: model.show_baseline,
+                                                    connect_toggled(sender) => move |check| {
+                                                        send!(sender, AppMsg::ToggleBaseline(check.is_active()));
+                                                    }
+                                                },
+                                            },
+                                            append = &gtk::Label {
+                                                set_margin_start: 5,
+                                                set_margin_end: 5,
+                                                set_halign: gtk::Align::Start,
+                                                set_visible: watch!(model.baseline.is_some()),
+                                                set_label: watch!(&model.baseline_report()),
+                                            },
+                                            append = &gtk::Label {
+                                                set_margin_start: 5,
+                                                set_margin_end: 5,
+                                                set_halign: gtk::Align::Start,
+                                                set_selectable: true,
+                                                set_tooltip_text: Some("Standard errors from the Jacobian at the end of the fit"),
+                                                set_visible: watch!(model.uncertainty.is_some()),
+                                                set_label: watch!(&model.uncertainty_report()),
+                                            },
+                                            append = &gtk::Label {
+                                                set_margin_start: 5,
+                                                set_margin_end: 5,
+                                                set_halign: gtk::Align::Start,
+                                                set_selectable: true,
+                                                set_tooltip_text: Some("Powder tensors; load a JSON file of powder radicals to change them"),
+                                                set_visible: watch!(model.is_powder() && !model.powder.is_empty()),
+                                                set_label: watch!(&model.powder_report()),
+                                            },
+                                            append: seed_entry = &gtk::Box {
+                                                set_orientation: gtk::Orientation::Horizontal,
+                                                set_spacing: 5,
+                                                set_margin_start: 5,
+                                                set_margin_end: 5,
+                                                set_margin_top: 5,
+                                                set_margin_bottom: 5,
+                                                set_visible: watch!(model.is_stochastic()),
+                                                append: &gtk::Label::new(Some("Seed")),
+                                                append = &gtk::SpinButton {
+                                                    set_width_chars: 10,
+                                                    set_tooltip_text: Some("Same seed and parameters, same run"),
+                                                    set_adjustment: &gtk::Adjustment::new(
+                                                        model.seed as f64,  // value
+                                                        0.0,  // lower
+                                                        u32::MAX as f64,  // upper
+                                                        1.0,  // step_increment
+                                                        10.0,  // page_increment
+                                                        0.0  // page_size
+                                                    ),
+                                                    set_value: watch!(model.seed as f64),
+                                                    connect_value_changed(sender) => move |val| {
+                                                        send!(sender, AppMsg::SetSeed(val.value() as u64));
+                                                    }
+                                                },
+                                            },
+                                            append: knots_entry = &gtk::Box {
+                                                set_orientation: gtk::Orientation::Horizontal,
+                                                set_spacing: 5,
+                                                set_margin_start: 5,
+                                                set_margin_end: 5,
+                                                set_margin_top: 5,
+                                                set_margin_bottom: 5,
+                                                set_visible: watch!(model.is_powder()),
+                                                append: &gtk::Label::new(Some("Powder knots")),
+                                                append: knots_spin = &gtk::SpinButton {
+                                                    set_width_chars: 5,
+                                                    set_tooltip_text: Some("Orientations between the pole and the equator"),
+                                                    set_adjustment: &gtk::Adjustment::new(
+                                                        model.powder_opts.knots as f64,  // value
+                                                        1.0,  // lower
+                                                        1000.0,  // upper
+                                                        1.0,  // step_increment
+                                                        10.0,  // page_increment
+                                                        100.0  // page_size
+                                                    ),
+                                                    set_value: watch!(model.powder_opts.knots as f64),
+                                                    connect_value_changed(sender) => move |val| {
+                                                        send!(sender, AppMsg::SetPowderKnots(val.value_as_int() as usize));
+                                                    }
+                                                },
+                                            },
+                                            append: annealing_entry = &gtk::Box {
+                                                set_orientation: gtk::Orientation::Horizontal,
+                                                set_spacing: 5,
+                                                set_margin_start: 5,
+                                                set_margin_end: 5,
+                                                set_margin_top: 5,
+                                                set_margin_bottom: 5,
+                                                set_visible: watch!(model.is_annealing()),
+                                                append: &gtk::Label::new(Some("Temperature")),
+                                                append = &gtk::SpinButton {
+                                                    set_width_chars: 5,
+                                                    set_digits: 3,
+                                                    set_tooltip_text: Some("Relative worsening of sigma accepted with probability 1/e"),
+                                                    set_adjustment: &gtk::Adjustment::new(
+                                                        model.annealing.temperature,  // value
+                                                        0.0,  // lower
+                                                        10.0,  // upper
+                                                        0.005,  // step_increment
+                                                        0.05,  // page_increment
+                                                        0.0  // page_size
+                                                    ),
+                                                    connect_value_changed(sender) => move |val| {
+                                                        send!(sender, AppMsg::SetTemperature(val.value()));
+                                                    }
+                                                },
+                                                append: &gtk::Label::new(Some("Cooling")),
+                                                append = &gtk::SpinButton {
+                                                    set_width_chars: 6,
+                                                    set_digits: 4,
+                                                    set_tooltip_text: Some("Temperature factor after every iteration"),
+                                                    set_adjustment: &gtk::Adjustment::new(
+                                                        model.annealing.cooling,  // value
+                                                        0.5,  // lower
+                                                        1.0,  // upper
+                                                        0.0005,  // step_increment
+                                                        0.005,  // page_increment
+                                                        0.0  // page_size
+                                                    ),
+                                                    connect_value_changed(sender) => move |val| {
+                                                        send!(sender, AppMsg::SetCooling(val.value()));
+                                                    }
+                                                },
+                                                append: &gtk::Label::new(Some("Reheats")),
+                                                append = &gtk::SpinButton {
+                                                    set_width_chars: 3,
+                                                    set_tooltip_text: Some("Restarts from the best, hot, when stuck"),
+                                                    set_adjustment: &gtk::Adjustment::new(
+                                                        model.annealing.reheats as f64,  // value
+                                                        0.0,  // lower
+                                                        100.0,  // upper
+                                                        1.0,  // step_increment
+                                                        5.0,  // page_increment
+                                                        0.0  // page_size
+                                                    ),
+                                                    connect_value_changed(sender) => move |val| {
+                                                        send!(sender, AppMsg::SetReheats(val.value_as_int() as usize));
+                                                    }
+                                                },
+                                            },
 
                                         }
                                     },
@@ -768,6 +1649,15 @@ impl Widgets<AppModel, ()> for AppWidgets {
                                 append = &adw::Bin {
                                     set_margin_bottom: 5,
                                     set_child = Some(&gtk::CenterBox) {
+                                        set_start_widget = Some(&gtk::Button) {
+                                            set_label: "Replay",
+                                            set_margin_start: 5,
+                                            set_tooltip_text: Some("Back to where the last run started, with its seed"),
+                                            set_sensitive: watch!(!model.montecarlo && model.run.is_some()),
+                                            connect_clicked(sender) => move |_| {
+                                                send!(sender, AppMsg::Replay);
+                                            }
+                                        },
                                         set_center_widget = Some(&gtk::ToggleButton) {
                                             set_label: "Run MonteCarlo",
                                             set_active: model.montecarlo,
@@ -780,6 +1670,15 @@ impl Widgets<AppModel, ()> for AppWidgets {
                                                     send!(sender, AppMsg::RefreshPanel)
                                                 };
                                             }
+                                        },
+                                        set_end_widget = Some(&gtk::Button) {
+                                            set_label: "Refine",
+                                            set_margin_end: 5,
+                                            set_tooltip_text: Some("Levenberg–Marquardt on the parameters with nonzero variation"),
+                                            set_sensitive: watch!(!model.montecarlo),
+                                            connect_clicked(sender) => move |_| {
+                                                send!(sender, AppMsg::Refine);
+                                            }
                                         }
                                     }
                                 },
@@ -820,6 +1719,23 @@ impl Widgets<AppModel, ()> for AppWidgets {
             send!(sender, AppMsg::ResetToast);
         }
 
+        self.positions_entry.set_active(Some(match model.sim_opts.positions {
+            LinePositions::FirstOrder => 0,
+            LinePositions::SecondOrder => 1,
+            LinePositions::BreitRabi => 2,
+        }));
+
+        self.baseline_entry.set_active(Some(match model.sim_opts.baseline {
+            Baseline::None => 0,
+            Baseline::Polynomial(order) => (order as u32 + 1).min(4),
+        }));
+
+        self.output_entry.set_active(Some(match model.sim_opts.output {
+            OutputMode::Absorption => 0,
+            OutputMode::FirstDerivative => 1,
+            OutputMode::SecondDerivative => 2,
+        }));
+
         // Double check if model and selected method are the same, then set it rightly
         // Could set the default in the widget itself and, then, remove this check
         match &model.sim_method {
@@ -831,6 +1747,15 @@ impl Widgets<AppModel, ()> for AppWidgets {
                     SimulationMethod::Dynamic1999 => {
                         self.sim_method_entry.set_active(Some(1));
                     }
+                    SimulationMethod::Powder => {
+                        self.sim_method_entry.set_active(Some(2));
+                    }
+                    SimulationMethod::Annealing => {
+                        self.sim_method_entry.set_active(Some(3));
+                    }
+                    SimulationMethod::NelderMead => {
+                        self.sim_method_entry.set_active(Some(4));
+                    }
                 }
             }
             None => {
@@ -917,12 +1842,25 @@ relm4::new_stateless_action!(ShowAboutAction, WindowActionGroup, "about");
 fn main() {
     let model = AppModel {
         empirical: None,
+        spectrum: None,
         rads: Vec::new(),
+        powder: Vec::new(),
+        powder_opts: PowderOptions::default(),
+        dynamic: Vec::new(),
+        annealing: Annealing::default(),
+        seed: rand::random::<u32>() as u64,
+        run: None,
+        optimizer: None,
+        uncertainty: None,
         points: 1024,
         sweep: 100.0,
+        sim_opts: SimOptions::default(),
         sigma: 100000000000000000000.0,  //1e+20
         iters: 0,
         montecarlo: false,
+        show_sticks: false,
+        show_baseline: false,
+        baseline: None,
         last_toast: None,
         log: Vec::new(),
         sim_method: Some(SimulationMethod::MC199),
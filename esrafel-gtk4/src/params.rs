@@ -2,7 +2,7 @@ use adw::prelude::BinExt;
 
 use gtk::{
     prelude::{BoxExt, FrameExt, ButtonExt, OrientableExt, ListModelExt, // EntryExt,
-              StaticType, ObjectExt, WidgetExt, GridExt},
+              StaticType, ObjectExt, WidgetExt, GridExt, ComboBoxExt, ComboBoxExtManual},
     gio, glib,
     };
 
@@ -12,7 +12,7 @@ use relm4::{
     factory::{Factory, FactoryVecDeque, FactoryPrototype, DynamicIndex, WeakDynamicIndex},
 };
 
-use libesrafel::{Radical, Nucleus, Param};
+use libesrafel::{Radical, Nucleus, Param, Lineshape};
 use crate::{AppModel, AppMsg};
 use crate::nuc_object::NucObject;
 
@@ -298,16 +298,23 @@ struct RadPar {
     value: u8,
     lwa_val: f64,
     lwa_var: f64,
-    // lwb_val: f64,
-    // lwb_var: f64,
-    // lwc_val: f64,
-    // lwc_var: f64,
+    lwb_val: f64,
+    lwb_var: f64,
+    lwc_val: f64,
+    lwc_var: f64,
     lrtz_val: f64,
     lrtz_var: f64,
     amount_val: f64,
     amount_var: f64,
     dh1_val: f64,
     dh1_var: f64,
+    g_val: f64,
+    g_var: f64,
+    lwl_val: f64,
+    lwl_var: f64,
+    lineshape: Lineshape,
+    phase_val: f64,
+    phase_var: f64,
     nuc_factory: MicroComponent<NucFactoryModel>,
 }
 
@@ -317,12 +324,23 @@ impl RadPar {
             value: v,
             lwa_val: 0.0,
             lwa_var: 0.0,
+            lwb_val: 0.0,
+            lwb_var: 0.0,
+            lwc_val: 0.0,
+            lwc_var: 0.0,
             lrtz_val: 50.0,
             lrtz_var: 0.0,
             amount_val: 100.0,
             amount_var: 0.0,
             dh1_val: 0.0,
             dh1_var: 0.0,
+            g_val: libesrafel::G_E,
+            g_var: 0.0,
+            lwl_val: 0.0,
+            lwl_var: 0.0,
+            lineshape: Lineshape::PseudoVoigt,
+            phase_val: 0.0,
+            phase_var: 0.0,
             nuc_factory: MicroComponent::new(NucFactoryModel::new(), ()),
         }
     }
@@ -349,6 +367,18 @@ impl RadPar {
         )
     }  // adjustment
 
+    // B and C can be negative
+    fn lw_m_adjustment() -> gtk::Adjustment {
+        gtk::Adjustment::new(
+            0.0,  // value
+            -1000.0,  // lower
+            1000.0,  // upper
+            0.1,  // step_increment
+            1.0,  // page_increment
+            10.0  // page_size
+        )
+    }  // adjustment
+
     fn amount_adjustment() -> gtk::Adjustment {
         gtk::Adjustment::new(
             100.0,  // value
@@ -382,6 +412,39 @@ impl RadPar {
         )
     }  // adjustment
 
+    fn phase_adjustment() -> gtk::Adjustment {
+        gtk::Adjustment::new(
+            0.0,  // value
+            -180.0,  // lower
+            180.0,  // upper
+            1.0,  // step_increment
+            10.0,  // page_increment
+            0.0  // page_size
+        )
+    }  // adjustment
+
+    fn g_adjustment() -> gtk::Adjustment {
+        gtk::Adjustment::new(
+            libesrafel::G_E,  // value
+            0.0,  // lower
+            10.0,  // upper
+            0.0001,  // step_increment
+            0.001,  // page_increment
+            0.01  // page_size
+        )
+    }  // adjustment
+
+    fn g_var_adjustment() -> gtk::Adjustment {
+        gtk::Adjustment::new(
+            0.0,  // value
+            0.0,  // lower
+            1.0,  // upper
+            0.0001,  // step_increment
+            0.001,  // page_increment
+            0.01  // page_size
+        )
+    }  // adjustment
+
     fn get_as_rad(&self) -> Radical {
         let nucs = match self.nuc_factory.model() {
             Ok(model) => { model.collect_nucs() }
@@ -393,9 +456,15 @@ impl RadPar {
 
         Radical {
             lwa: Param::set(self.lwa_val, self.lwa_var),
+            lwb: Param::set(self.lwb_val, self.lwb_var),
+            lwc: Param::set(self.lwc_val, self.lwc_var),
             lrtz: Param::set(self.lrtz_val, self.lrtz_var),
+            lwl: Param::set(self.lwl_val, self.lwl_var),
+            lineshape: self.lineshape,
+            phase: Param::set(self.phase_val, self.phase_var),
             amount: Param::set(self.amount_val, self.amount_var),
             dh1: Param::set(self.dh1_val, self.dh1_var),
+            g: Param::set(self.g_val, self.g_var),
             nucs,
         }
     }
@@ -403,12 +472,23 @@ impl RadPar {
     fn from_rad(&mut self, rad: &Radical) {
         self.lwa_val = rad.lwa.val;
         self.lwa_var = rad.lwa.var;
+        self.lwb_val = rad.lwb.val;
+        self.lwb_var = rad.lwb.var;
+        self.lwc_val = rad.lwc.val;
+        self.lwc_var = rad.lwc.var;
         self.lrtz_val = rad.lrtz.val;
         self.lrtz_var = rad.lrtz.var;
         self.amount_val = rad.amount.val;
         self.amount_var = rad.amount.var;
         self.dh1_val = rad.dh1.val;
         self.dh1_var = rad.dh1.var;
+        self.g_val = rad.g.val;
+        self.g_var = rad.g.var;
+        self.lwl_val = rad.lwl.val;
+        self.lwl_var = rad.lwl.var;
+        self.lineshape = rad.lineshape;
+        self.phase_val = rad.phase.val;
+        self.phase_var = rad.phase.var;
 
         // Set nuc values for every single nuc in the model
         match self.nuc_factory.model() {
@@ -473,12 +553,23 @@ pub enum RadParMsg {
     _InsertAfter(WeakDynamicIndex),
     SetLwaVal(WeakDynamicIndex, f64),
     SetLwaVar(WeakDynamicIndex, f64),
+    SetLwbVal(WeakDynamicIndex, f64),
+    SetLwbVar(WeakDynamicIndex, f64),
+    SetLwcVal(WeakDynamicIndex, f64),
+    SetLwcVar(WeakDynamicIndex, f64),
     SetLrtzVal(WeakDynamicIndex, f64),
     SetLrtzVar(WeakDynamicIndex, f64),
     SetAmountVal(WeakDynamicIndex, f64),
     SetAmountVar(WeakDynamicIndex, f64),
     SetDh1Val(WeakDynamicIndex, f64),
     SetDh1Var(WeakDynamicIndex, f64),
+    SetGVal(WeakDynamicIndex, f64),
+    SetGVar(WeakDynamicIndex, f64),
+    SetLwlVal(WeakDynamicIndex, f64),
+    SetLwlVar(WeakDynamicIndex, f64),
+    SetLineshape(WeakDynamicIndex, Lineshape),
+    SetPhaseVal(WeakDynamicIndex, f64),
+    SetPhaseVar(WeakDynamicIndex, f64),
     AddNuc(WeakDynamicIndex, String),
     RemoveLastNuc(WeakDynamicIndex),
 }
@@ -585,6 +676,34 @@ impl ComponentUpdate<AppModel> for RadParModel {
                     }
                 }
             }
+            RadParMsg::SetLwbVal(weak_index, val) => {
+                if let Some(index) = weak_index.upgrade() {
+                    if let Some(counter) = self.pars.get_mut(index.current_index()) {
+                        counter.lwb_val = val;
+                    }
+                }
+            }
+            RadParMsg::SetLwbVar(weak_index, val) => {
+                if let Some(index) = weak_index.upgrade() {
+                    if let Some(counter) = self.pars.get_mut(index.current_index()) {
+                        counter.lwb_var = val;
+                    }
+                }
+            }
+            RadParMsg::SetLwcVal(weak_index, val) => {
+                if let Some(index) = weak_index.upgrade() {
+                    if let Some(counter) = self.pars.get_mut(index.current_index()) {
+                        counter.lwc_val = val;
+                    }
+                }
+            }
+            RadParMsg::SetLwcVar(weak_index, val) => {
+                if let Some(index) = weak_index.upgrade() {
+                    if let Some(counter) = self.pars.get_mut(index.current_index()) {
+                        counter.lwc_var = val;
+                    }
+                }
+            }
             RadParMsg::SetLrtzVal(weak_index, val) => {
                 if let Some(index) = weak_index.upgrade() {
                     if let Some(counter) = self.pars.get_mut(index.current_index()) {
@@ -627,6 +746,55 @@ impl ComponentUpdate<AppModel> for RadParModel {
                     }
                 }
             }
+            RadParMsg::SetGVal(weak_index, val) => {
+                if let Some(index) = weak_index.upgrade() {
+                    if let Some(counter) = self.pars.get_mut(index.current_index()) {
+                        counter.g_val = val;
+                    }
+                }
+            }
+            RadParMsg::SetGVar(weak_index, val) => {
+                if let Some(index) = weak_index.upgrade() {
+                    if let Some(counter) = self.pars.get_mut(index.current_index()) {
+                        counter.g_var = val;
+                    }
+                }
+            }
+            RadParMsg::SetLwlVal(weak_index, val) => {
+                if let Some(index) = weak_index.upgrade() {
+                    if let Some(counter) = self.pars.get_mut(index.current_index()) {
+                        counter.lwl_val = val;
+                    }
+                }
+            }
+            RadParMsg::SetLwlVar(weak_index, val) => {
+                if let Some(index) = weak_index.upgrade() {
+                    if let Some(counter) = self.pars.get_mut(index.current_index()) {
+                        counter.lwl_var = val;
+                    }
+                }
+            }
+            RadParMsg::SetLineshape(weak_index, val) => {
+                if let Some(index) = weak_index.upgrade() {
+                    if let Some(counter) = self.pars.get_mut(index.current_index()) {
+                        counter.lineshape = val;
+                    }
+                }
+            }
+            RadParMsg::SetPhaseVal(weak_index, val) => {
+                if let Some(index) = weak_index.upgrade() {
+                    if let Some(counter) = self.pars.get_mut(index.current_index()) {
+                        counter.phase_val = val;
+                    }
+                }
+            }
+            RadParMsg::SetPhaseVar(weak_index, val) => {
+                if let Some(index) = weak_index.upgrade() {
+                    if let Some(counter) = self.pars.get_mut(index.current_index()) {
+                        counter.phase_var = val;
+                    }
+                }
+            }
             RadParMsg::AddNuc(weak_index, val) => {
                 if let Some(index) = weak_index.upgrade() {
                     if let Some(counter) = self.pars.get_mut(index.current_index()) {
@@ -784,12 +952,54 @@ impl FactoryPrototype for RadPar {
                                                 send!(sender, RadParMsg::SetLwaVar(key.downgrade(), val.value()));
                                             }
                                         },
-                                    attach(0, 2, 1, 1): lrtz_label = &gtk::Label {
+                                    attach(0, 2, 1, 1): lwb_label = &gtk::Label {
+                                        set_label: "Width B (·M)",
+                                        set_halign: gtk::Align::Start,
+                                    },
+                                    attach(1, 2, 1, 1): lwb_entry_val = &gtk::SpinButton {
+                                        set_adjustment: &RadPar::lw_m_adjustment(),
+                                        set_digits: 2,
+                                        set_value: watch!(self.lwb_val),
+                                        connect_value_changed(sender, key) => move |val| {
+                                            send!(sender, RadParMsg::SetLwbVal(key.downgrade(), val.value()));
+                                        }
+                                    },
+                                    attach(2, 2, 1, 1): lwb_entry_var = &gtk::SpinButton {
+                                        set_adjustment: &RadPar::var_adjustment(),
+                                        set_digits: 2,
+                                        set_climb_rate: 0.5,
+                                        set_value: watch!(self.lwb_var),
+                                        connect_value_changed(sender, key) => move |val| {
+                                            send!(sender, RadParMsg::SetLwbVar(key.downgrade(), val.value()));
+                                        }
+                                    },
+                                    attach(0, 3, 1, 1): lwc_label = &gtk::Label {
+                                        set_label: "Width C (·M²)",
+                                        set_halign: gtk::Align::Start,
+                                    },
+                                    attach(1, 3, 1, 1): lwc_entry_val = &gtk::SpinButton {
+                                        set_adjustment: &RadPar::lw_m_adjustment(),
+                                        set_digits: 2,
+                                        set_value: watch!(self.lwc_val),
+                                        connect_value_changed(sender, key) => move |val| {
+                                            send!(sender, RadParMsg::SetLwcVal(key.downgrade(), val.value()));
+                                        }
+                                    },
+                                    attach(2, 3, 1, 1): lwc_entry_var = &gtk::SpinButton {
+                                        set_adjustment: &RadPar::var_adjustment(),
+                                        set_digits: 2,
+                                        set_climb_rate: 0.5,
+                                        set_value: watch!(self.lwc_var),
+                                        connect_value_changed(sender, key) => move |val| {
+                                            send!(sender, RadParMsg::SetLwcVar(key.downgrade(), val.value()));
+                                        }
+                                    },
+                                    attach(0, 4, 1, 1): lrtz_label = &gtk::Label {
                                         set_label: "Shape (Lrtz/Gauss)",
                                         set_halign: gtk::Align::Start,
                                         set_margin_end: 15,
                                     },
-                                    attach(1, 2, 1, 1): lrtz_entry_val = &gtk::SpinButton {
+                                    attach(1, 4, 1, 1): lrtz_entry_val = &gtk::SpinButton {
                                         set_adjustment: &RadPar::lrtz_adjustment(),
                                         set_digits: 1,
                                         set_value: watch!(self.lrtz_val),
@@ -797,7 +1007,7 @@ impl FactoryPrototype for RadPar {
                                             send!(sender, RadParMsg::SetLrtzVal(key.downgrade(), val.value()));
                                         }
                                     },
-                                    attach(2, 2, 1, 1): lrtz_entry_var = &gtk::SpinButton {
+                                    attach(2, 4, 1, 1): lrtz_entry_var = &gtk::SpinButton {
                                         set_adjustment: &RadPar::var_adjustment(),
                                         set_digits: 1,
                                         set_value: watch!(self.lrtz_var),
@@ -806,11 +1016,11 @@ impl FactoryPrototype for RadPar {
                                             send!(sender, RadParMsg::SetLrtzVar(key.downgrade(), val.value()));
                                         }
                                     },
-                                    attach(0, 3, 1, 1): amount_label = &gtk::Label {
+                                    attach(0, 5, 1, 1): amount_label = &gtk::Label {
                                         set_label: "Amount (%)",
                                         set_halign: gtk::Align::Start,
                                     },
-                                    attach(1, 3, 1, 1): amount_entry_val = &gtk::SpinButton {
+                                    attach(1, 5, 1, 1): amount_entry_val = &gtk::SpinButton {
                                         set_adjustment: &RadPar::amount_adjustment(),
                                         set_digits: 1,
                                         set_value: watch!(self.amount_val),
@@ -818,7 +1028,7 @@ impl FactoryPrototype for RadPar {
                                             send!(sender, RadParMsg::SetAmountVal(key.downgrade(), val.value()));
                                         }
                                     },
-                                    attach(2, 3, 1, 1): amount_entry_var = &gtk::SpinButton {
+                                    attach(2, 5, 1, 1): amount_entry_var = &gtk::SpinButton {
                                         set_adjustment: &RadPar::var_adjustment(),
                                         set_digits: 1,
                                         set_value: watch!(self.amount_var),
@@ -827,11 +1037,11 @@ impl FactoryPrototype for RadPar {
                                             send!(sender, RadParMsg::SetAmountVar(key.downgrade(), val.value()));
                                         }
                                     },
-                                    attach(0, 4, 1, 1): dh1_label = &gtk::Label {
+                                    attach(0, 6, 1, 1): dh1_label = &gtk::Label {
                                         set_label: "Center",
                                         set_halign: gtk::Align::Start,
                                     },
-                                    attach(1, 4, 1, 1): dh1_entry_val = &gtk::SpinButton {
+                                    attach(1, 6, 1, 1): dh1_entry_val = &gtk::SpinButton {
                                         set_adjustment: &RadPar::dh1_adjustment(),
                                         set_digits: 1,
                                         set_value: watch!(self.dh1_val),
@@ -839,7 +1049,7 @@ impl FactoryPrototype for RadPar {
                                             send!(sender, RadParMsg::SetDh1Val(key.downgrade(), val.value()));
                                         }
                                     },
-                                    attach(2, 4, 1, 1): dh1_entry_var = &gtk::SpinButton {
+                                    attach(2, 6, 1, 1): dh1_entry_var = &gtk::SpinButton {
                                         set_adjustment: &RadPar::var_adjustment(),
                                         set_digits: 1,
                                         set_value: watch!(self.dh1_var),
@@ -848,6 +1058,90 @@ impl FactoryPrototype for RadPar {
                                             send!(sender, RadParMsg::SetDh1Var(key.downgrade(), val.value()));
                                         }
                                     },
+                                    attach(0, 7, 1, 1): g_label = &gtk::Label {
+                                        set_label: "g-factor",
+                                        set_halign: gtk::Align::Start,
+                                    },
+                                    attach(1, 7, 1, 1): g_entry_val = &gtk::SpinButton {
+                                        set_adjustment: &RadPar::g_adjustment(),
+                                        set_digits: 5,
+                                        set_value: watch!(self.g_val),
+                                        connect_value_changed(sender, key) => move |val| {
+                                            send!(sender, RadParMsg::SetGVal(key.downgrade(), val.value()));
+                                        }
+                                    },
+                                    attach(2, 7, 1, 1): g_entry_var = &gtk::SpinButton {
+                                        set_adjustment: &RadPar::g_var_adjustment(),
+                                        set_digits: 5,
+                                        set_value: watch!(self.g_var),
+                                        connect_value_changed(sender, key) => move |val| {
+                                            send!(sender, RadParMsg::SetGVar(key.downgrade(), val.value()));
+                                        }
+                                    },
+                                    attach(0, 8, 1, 1): lwl_label = &gtk::Label {
+                                        set_label: "Lorentzian width",
+                                        set_halign: gtk::Align::Start,
+                                    },
+                                    attach(1, 8, 1, 1): lwl_entry_val = &gtk::SpinButton {
+                                        set_adjustment: &RadPar::lwa_adjustment(),
+                                        set_digits: 1,
+                                        set_value: watch!(self.lwl_val),
+                                        set_climb_rate: 0.5,
+                                        set_sensitive: watch!(self.lineshape == Lineshape::Voigt),
+                                        connect_value_changed(sender, key) => move |val| {
+                                            send!(sender, RadParMsg::SetLwlVal(key.downgrade(), val.value()));
+                                        }
+                                    },
+                                    attach(2, 8, 1, 1): lwl_entry_var = &gtk::SpinButton {
+                                        set_adjustment: &RadPar::var_adjustment(),
+                                        set_digits: 1,
+                                        set_value: watch!(self.lwl_var),
+                                        set_climb_rate: 0.5,
+                                        set_sensitive: watch!(self.lineshape == Lineshape::Voigt),
+                                        connect_value_changed(sender, key) => move |val| {
+                                            send!(sender, RadParMsg::SetLwlVar(key.downgrade(), val.value()));
+                                        }
+                                    },
+                                    attach(0, 9, 1, 1): lineshape_label = &gtk::Label {
+                                        set_label: "Lineshape",
+                                        set_halign: gtk::Align::Start,
+                                    },
+                                    attach(1, 9, 2, 1): lineshape_entry = &gtk::ComboBoxText {
+                                        append_text: "Pseudo-Voigt",
+                                        append_text: "Voigt",
+                                        set_active: watch!(Some(match self.lineshape {
+                                            Lineshape::PseudoVoigt => 0,
+                                            Lineshape::Voigt => 1,
+                                        })),
+                                        connect_changed(sender, key) => move |selector| {
+                                            let lineshape = match selector.active() {
+                                                Some(1) => Lineshape::Voigt,
+                                                _ => Lineshape::PseudoVoigt,
+                                            };
+                                            send!(sender, RadParMsg::SetLineshape(key.downgrade(), lineshape));
+                                        }
+                                    },
+                                    attach(0, 10, 1, 1): phase_label = &gtk::Label {
+                                        set_label: "Phase (°)",
+                                        set_halign: gtk::Align::Start,
+                                    },
+                                    attach(1, 10, 1, 1): phase_entry_val = &gtk::SpinButton {
+                                        set_adjustment: &RadPar::phase_adjustment(),
+                                        set_digits: 1,
+                                        set_value: watch!(self.phase_val),
+                                        connect_value_changed(sender, key) => move |val| {
+                                            send!(sender, RadParMsg::SetPhaseVal(key.downgrade(), val.value()));
+                                        }
+                                    },
+                                    attach(2, 10, 1, 1): phase_entry_var = &gtk::SpinButton {
+                                        set_adjustment: &RadPar::var_adjustment(),
+                                        set_digits: 1,
+                                        set_value: watch!(self.phase_var),
+                                        set_climb_rate: 0.5,
+                                        connect_value_changed(sender, key) => move |val| {
+                                            send!(sender, RadParMsg::SetPhaseVar(key.downgrade(), val.value()));
+                                        }
+                                    },
                                 },  // Grid
                             },
 
@@ -0,0 +1,208 @@
+// One dispatch for every simulation method: the GUI and the Python bindings pick
+// a `SimulationMethod`, get an `Optimizer` from it, and step it until they're happy.
+// A new backend is a new `Model` variant, a new method, or both.
+use crate::Radical;
+use crate::dynamic::{self, ExchangeRadical};
+use crate::eprft::{self, AnnealState, Annealing, SimOptions};
+use crate::fit::{self, Simplex};
+use crate::powder::{self, PowderOptions, PowderRadical};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use serde::{Serialize, Deserialize};
+
+// Available simulation methods
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SimulationMethod {
+    #[default]
+    MC199,  // ESR Commander's random walk
+    Dynamic1999,  // Two-site exchange, alternating linewidths
+    Powder,  // Anisotropic g and A tensors
+    Annealing,  // Same radicals as MC 1999, moving uphill too
+    NelderMead,  // Same radicals, downhill simplex
+}
+
+// Anything that draws a spectrum on a field axis
+pub trait SpectrumModel {
+    fn simulate(&self, fld: &[f64], opts: &SimOptions) -> Vec<f64>;
+}
+
+impl SpectrumModel for [Radical] {
+    fn simulate(&self, fld: &[f64], opts: &SimOptions) -> Vec<f64> {
+        eprft::calcola_fld(self, fld, opts)
+    }
+}
+
+impl SpectrumModel for [ExchangeRadical] {
+    fn simulate(&self, fld: &[f64], opts: &SimOptions) -> Vec<f64> {
+        dynamic::calcola(self, fld, opts)
+    }
+}
+
+// Powder radicals need the orientations too
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Powder {
+    pub rads: Vec<PowderRadical>,
+    pub opts: PowderOptions,
+}
+
+impl SpectrumModel for Powder {
+    fn simulate(&self, fld: &[f64], opts: &SimOptions) -> Vec<f64> {
+        powder::calcola(&self.rads, fld, opts, &self.opts)
+    }
+}
+
+// The radicals of every backend
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Model {
+    Isotropic(Vec<Radical>),
+    Dynamic(Vec<ExchangeRadical>),
+    Powder(Powder),
+}
+
+impl SpectrumModel for Model {
+    fn simulate(&self, fld: &[f64], opts: &SimOptions) -> Vec<f64> {
+        match self {
+            Model::Isotropic(rads) => rads.simulate(fld, opts),
+            Model::Dynamic(rads) => rads.simulate(fld, opts),
+            Model::Powder(powder) => powder.simulate(fld, opts),
+        }
+    }
+}
+
+// A fit, one iteration at a time
+pub trait Optimizer {
+    // Move once against `empirical`, on its field axis `fld`;
+    // returns the normalized spectrum of the step, to plot, like `mc_fit`
+    fn step(&mut self, empirical: &[f64], fld: &[f64], opts: &SimOptions) -> Vec<f64>;
+    fn best_sigma(&self) -> f64;
+    fn best(&self) -> Model;
+    // Further steps won't change anything
+    fn converged(&self) -> bool {
+        false
+    }
+}
+
+// The 1999 random walk, for any model.
+// The same `seed` replays the same walk
+pub struct MonteCarlo {
+    model: Model,
+    sigma: f64,
+    rng: StdRng,
+}
+
+impl MonteCarlo {
+    pub fn new(model: Model, sigma: f64, seed: u64) -> Self {
+        MonteCarlo { model, sigma, rng: StdRng::seed_from_u64(seed) }
+    }
+}
+
+impl Optimizer for MonteCarlo {
+    fn step(&mut self, empirical: &[f64], fld: &[f64], opts: &SimOptions) -> Vec<f64> {
+        let (sigma, newteor) = match &mut self.model {
+            Model::Isotropic(rads) => {
+                let (sigma, newteor, newrads) = eprft::mc_fit(empirical, fld, opts, self.sigma, rads.clone(), &mut self.rng);
+                *rads = newrads;
+                (sigma, newteor)
+            }
+            Model::Dynamic(rads) => {
+                let (sigma, newteor, newrads) = dynamic::mc_fit(empirical, fld, opts, self.sigma, rads.clone(), &mut self.rng);
+                *rads = newrads;
+                (sigma, newteor)
+            }
+            Model::Powder(powder) => {
+                let (sigma, newteor, newrads) =
+                    powder::mc_fit(empirical, fld, opts, &powder.opts, self.sigma, powder.rads.clone(), &mut self.rng);
+                powder.rads = newrads;
+                (sigma, newteor)
+            }
+        };
+        self.sigma = sigma;
+        newteor
+    }
+
+    fn best_sigma(&self) -> f64 {
+        self.sigma
+    }
+
+    fn best(&self) -> Model {
+        self.model.clone()
+    }
+}
+
+// Simulated annealing of isotropic radicals
+pub struct Annealer {
+    schedule: Annealing,
+    state: AnnealState<Radical>,
+    rng: StdRng,
+}
+
+impl Annealer {
+    pub fn new(rads: Vec<Radical>, sigma: f64, schedule: &Annealing, seed: u64) -> Self {
+        Annealer {
+            schedule: schedule.clone(),
+            state: AnnealState::new(rads, sigma, schedule),
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl Optimizer for Annealer {
+    fn step(&mut self, empirical: &[f64], fld: &[f64], opts: &SimOptions) -> Vec<f64> {
+        let (state, newteor) = eprft::anneal(empirical, fld, opts, &self.schedule, self.state.clone(), &mut self.rng);
+        self.state = state;
+        newteor
+    }
+
+    fn best_sigma(&self) -> f64 {
+        self.state.best_sigma
+    }
+
+    fn best(&self) -> Model {
+        Model::Isotropic(self.state.best.clone())
+    }
+}
+
+impl Optimizer for Simplex {
+    fn step(&mut self, empirical: &[f64], fld: &[f64], opts: &SimOptions) -> Vec<f64> {
+        fit::nelder_mead(empirical, fld, opts, self).1
+    }
+
+    fn best_sigma(&self) -> f64 {
+        Simplex::sigma(self)
+    }
+
+    fn best(&self) -> Model {
+        Model::Isotropic(self.rads())
+    }
+
+    fn converged(&self) -> bool {
+        Simplex::converged(self)
+    }
+}
+
+impl SimulationMethod {
+    // Which radicals the method works on
+    pub fn is_isotropic(self) -> bool {
+        !matches!(self, SimulationMethod::Dynamic1999 | SimulationMethod::Powder)
+    }
+
+    // A new fit with this method, from `model` with sigma `sigma`; random moves are drawn from `seed`.
+    // Annealing and Nelder–Mead only know isotropic radicals: other models get the random walk.
+    #[allow(clippy::too_many_arguments)]
+    pub fn optimizer(
+        self,
+        model: Model,
+        sigma: f64,
+        schedule: &Annealing,
+        seed: u64,
+        empirical: &[f64],
+        fld: &[f64],
+        opts: &SimOptions) -> Box<dyn Optimizer> {
+
+        match (self, model) {
+            (SimulationMethod::Annealing, Model::Isotropic(rads)) => Box::new(Annealer::new(rads, sigma, schedule, seed)),
+            (SimulationMethod::NelderMead, Model::Isotropic(rads)) => Box::new(Simplex::new(empirical, fld, opts, rads)),
+            (_, model) => Box::new(MonteCarlo::new(model, sigma, seed)),
+        }
+    }
+}
@@ -0,0 +1,82 @@
+// Bessel functions of the first kind, integer order, for field modulation.
+// Miller's backward recurrence for small arguments, Hankel's asymptotic
+// expansion for large ones; both good to ~1e-10.
+
+pub(crate) fn jn(n: u32, x: f64) -> f64 {
+    if x < 0.0 {
+        let j = jn(n, -x);
+        return if n.is_multiple_of(2) { j } else { -j };
+    }
+    if x == 0.0 {
+        return if n == 0 { 1.0 } else { 0.0 };
+    }
+
+    let n2 = (n * n) as f64;
+    if x > 25.0 + n2 {
+        asymptotic(n, x)
+    } else {
+        miller(n, x)
+    }
+}
+
+// Downward recurrence J(k-1) = 2k/x J(k) - J(k+1) from far above n,
+// normalized with J0 + 2 Σ J2k = 1
+fn miller(n: u32, x: f64) -> f64 {
+    let top = (n as f64).max(x);
+    let m = 2 * ((top + (40.0 * top).sqrt()) as usize / 2 + 10);
+
+    let (mut bjp, mut bj) = (0.0, 1.0);
+    let mut sum = 0.0;
+    let mut ans = 0.0;
+    let mut even = false;
+
+    for j in (1..=m).rev() {
+        let bjm = 2.0 * j as f64 / x * bj - bjp;
+        bjp = bj;
+        bj = bjm;
+
+        // Keep away from overflow
+        if bj.abs() > 1e10 {
+            bj *= 1e-10;
+            bjp *= 1e-10;
+            ans *= 1e-10;
+            sum *= 1e-10;
+        }
+
+        if even {
+            sum += bj;
+        }
+        even = !even;
+        if j == n as usize {
+            ans = bjp;
+        }
+    }
+
+    // bj is J0 now, and was counted among the even terms
+    let norm = 2.0 * sum - bj;
+    if n == 0 { bj / norm } else { ans / norm }
+}
+
+// J(x) ~ √(2/πx) (P cos χ - Q sin χ), χ = x - nπ/2 - π/4
+fn asymptotic(n: u32, x: f64) -> f64 {
+    let mu = 4.0 * (n * n) as f64;
+    let z = 8.0 * x;
+
+    let (mut p, mut q) = (0.0, 0.0);
+    let mut term = 1.0;
+    for k in 0..12 {
+        if k % 2 == 0 {
+            p += if k % 4 == 0 { term } else { -term };
+        } else {
+            q += if k % 4 == 1 { term } else { -term };
+        }
+        let odd = (2 * k + 1) as f64;
+        term *= (mu - odd * odd) / ((k + 1) as f64 * z);
+        if term.abs() < 1e-17 {
+            break;
+        }
+    }
+
+    let chi = x - (n as f64 / 2.0 + 0.25) * std::f64::consts::PI;
+    (2.0 / (std::f64::consts::PI * x)).sqrt() * (p * chi.cos() - q * chi.sin())
+}
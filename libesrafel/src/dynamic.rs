@@ -0,0 +1,203 @@
+// Two-site exchange: every radical jumps between two conformations, A and B,
+// each with its own hyperfine set. Nuclear spin states survive the jump, so every
+// combination of M_I is a line exchanging between its A and B positions,
+// solved with the modified Bloch equations (McConnell).
+// Lines of the groups whose couplings differ between sites get broadened,
+// the others stay sharp: that's the linewidth alternation of ESR Commander's Dynamic method.
+// Intrinsic lines are Lorentzian, hyperfine positions first order.
+use crate::{Param, Radical};
+use crate::eprft::{mc_fit_with, modulated, resonance_field, OutputMode, SimOptions, G_MIN, H_OVER_MUB};
+use crate::complex::Complex;
+use crate::hyperfine::multiplet;
+use rand::rngs::StdRng;
+use serde::{Serialize, Deserialize};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExchangeNucleus {
+    pub spin: Param,
+    pub eqs: Param,
+    pub hpf_a: Param,  // Hyperfine constant in site A [G]
+    pub hpf_b: Param,  // ... and in site B
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExchangeRadical {
+    pub lwa: Param,  // Peak to peak Lorentzian line width without exchange [G]
+    pub amount: Param,  // Relative amount
+    pub dh1: Param,  // Field offset [G]
+    #[serde(default = "crate::free_electron")]
+    pub g: Param,
+    pub rate: Param,  // Exchange rate [s⁻¹]: A to B at 2·rate·pB, B to A at 2·rate·pA
+    #[serde(default = "half")]
+    pub population: Param,  // Fraction of the radicals in site A
+    pub nucs: Vec<ExchangeNucleus>,
+}
+
+fn half() -> Param {
+    Param::set(0.5, 0.0)
+}
+
+// Same couplings in both sites and no exchange; a starting point for alternating couplings
+impl From<&Radical> for ExchangeRadical {
+    fn from(rad: &Radical) -> Self {
+        ExchangeRadical {
+            lwa: rad.lwa.clone(),
+            amount: rad.amount.clone(),
+            dh1: rad.dh1.clone(),
+            g: rad.g.clone(),
+            rate: Param::set(0.0, 0.0),
+            population: half(),
+            nucs: rad.nucs.iter().map(|nuc| ExchangeNucleus {
+                spin: nuc.spin.clone(),
+                eqs: nuc.eqs.clone(),
+                hpf_a: nuc.hpf.clone(),
+                hpf_b: nuc.hpf.clone(),
+            }).collect(),
+        }
+    }
+}
+
+// (position in A, position in B, intensity) of every combination of M_I, around zero
+fn line_pairs(rad: &ExchangeRadical) -> Vec<(f64, f64, f64)> {
+    let mut pairs = vec![(0.0, 0.0, 1.0)];
+
+    for nuc in &rad.nucs {
+        let eqs = nuc.eqs.val.round().max(0.0) as usize;
+        let two_i = (2.0 * nuc.spin.val).round().max(0.0) as usize;
+        if eqs == 0 || two_i == 0 {
+            continue;
+        }
+
+        let counts = multiplet(two_i, eqs);
+        let half = (counts.len() - 1) as f64 / 2.0;
+        pairs = pairs.iter().flat_map(|(a, b, int)| {
+            counts.iter().enumerate().map(move |(k, c)| {
+                let m = k as f64 - half;
+                (a - nuc.hpf_a.val * m, b - nuc.hpf_b.val * m, int * c)
+            })
+        }).collect();
+    }
+    pairs
+}
+
+// Steady state of the Bloch equations of a line jumping between A and B:
+// with αj = Γ + i(B - Bj), (αA + kA) mA - kB mB = pA and -kA mA + (αB + kB) mB = pB;
+// mA + mB = N/D, whose real part is a Lorentzian of area π without exchange.
+// Derivatives along the field come from dα/dB = i.
+// Linewidth, rates [G] and population shared by all the lines of a radical
+struct Exchange {
+    gamma: f64,
+    k_a: f64,
+    k_b: f64,
+    p_a: f64,
+}
+
+fn exchange_line(b: f64, b_a: f64, b_b: f64, ex: &Exchange, output: OutputMode) -> f64 {
+    let alpha_a = Complex::new(ex.gamma, b - b_a);
+    let alpha_b = Complex::new(ex.gamma, b - b_b);
+    let k = ex.k_a + ex.k_b;
+
+    let n = (alpha_b + k).scale(ex.p_a) + (alpha_a + k).scale(1.0 - ex.p_a);
+    let d = alpha_a * alpha_b + alpha_a.scale(ex.k_b) + alpha_b.scale(ex.k_a);
+
+    let dn = Complex::I;
+    let dd = Complex::I * (alpha_a + alpha_b + k);
+    let ddd = Complex::new(-2.0, 0.0);
+
+    let f = match output {
+        OutputMode::Absorption => n / d,
+        OutputMode::FirstDerivative => (dn * d - n * dd) / (d * d),
+        OutputMode::SecondDerivative => {
+            -(n * ddd) / (d * d) - (dd * (dn * d - n * dd)).scale(2.0) / (d * d * d)
+        }
+    };
+    f.re
+}
+
+// Calculate the exchange broadened spectrum on a field axis [G]; like `eprft::calcola_fld`,
+// without the microwave frequency every radical sits at the center of the axis
+pub fn calcola(rads: &[ExchangeRadical], fld: &[f64], sim: &SimOptions) -> Vec<f64> {
+    let mut newteor = vec![0.0; fld.len()];
+    if fld.is_empty() {
+        return newteor;
+    }
+
+    if let Some(modulation) = sim.modulation.as_ref().filter(|m| m.amplitude > 0.0 && fld.len() > 1) {
+        let absorption = SimOptions { output: OutputMode::Absorption, modulation: None, ..sim.clone() };
+        return modulated(fld, modulation, |grid| calcola(rads, grid, &absorption));
+    }
+
+    let lo = fld.iter().cloned().fold(f64::INFINITY, f64::min);
+    let hi = fld.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+
+    for rad in rads {
+        if rad.lwa.val <= 0.0 {
+            continue;
+        }
+
+        let b0 = match sim.mwfq {
+            Some(mwfq) => resonance_field(rad.g.val, mwfq),
+            None => (lo + hi) / 2.0,
+        } + rad.dh1.val;
+
+        // Rates from s⁻¹ to gauss, through the gyromagnetic ratio of this g
+        let gyro = 2.0 * std::f64::consts::PI * 1e9 * rad.g.val / H_OVER_MUB;
+        let p_a = rad.population.val.clamp(0.0, 1.0);
+        let ex = Exchange {
+            gamma: 3.0_f64.sqrt() / 2.0 * rad.lwa.val,
+            k_a: 2.0 * rad.rate.val.max(0.0) * (1.0 - p_a) / gyro,
+            k_b: 2.0 * rad.rate.val.max(0.0) * p_a / gyro,
+            p_a,
+        };
+
+        let pairs = line_pairs(rad);
+        let totale: f64 = pairs.iter().map(|(_, _, int)| int).sum();
+        let scale = rad.amount.val / (std::f64::consts::PI * totale);
+
+        for (a, b, int) in &pairs {
+            for (point, x) in fld.iter().enumerate() {
+                newteor[point] += scale * int * exchange_line(*x, b0 + a, b0 + b, &ex, sim.output);
+            }
+        }
+    }
+
+    newteor
+}
+
+// Reset potentially aberrant values, like `eprft` does
+fn check_pars(mut rad: ExchangeRadical) -> ExchangeRadical {
+    if rad.lwa.val < 0.0 { rad.lwa.val = 0.0 };
+    if rad.amount.val < 0.0 { rad.amount.val = 0.0 };
+    if rad.rate.val < 0.0 { rad.rate.val = 0.0 };
+    rad.population.val = rad.population.val.clamp(0.0, 1.0);
+    if rad.g.val < G_MIN { rad.g.val = G_MIN };
+    rad
+}
+
+fn caso(rads: &[ExchangeRadical], rng: &mut StdRng) -> Vec<ExchangeRadical> {
+    rads.iter().cloned().map(|mut rad| {
+        rad.lwa = rad.lwa.randomize(rng);
+        rad.amount = rad.amount.randomize(rng);
+        rad.dh1 = rad.dh1.randomize(rng);
+        rad.g = rad.g.randomize(rng);
+        rad.rate = rad.rate.randomize(rng);
+        rad.population = rad.population.randomize(rng);
+        for nuc in rad.nucs.iter_mut() {
+            nuc.hpf_a = nuc.hpf_a.randomize(rng);
+            nuc.hpf_b = nuc.hpf_b.randomize(rng);
+        }
+        check_pars(rad)
+    }).collect()
+}
+
+// One Monte Carlo iteration, as `eprft::mc_fit`
+pub fn mc_fit(
+    empirical: &[f64],
+    fld: &[f64],
+    sim: &SimOptions,
+    sigma: f64,
+    rads: Vec<ExchangeRadical>,
+    rng: &mut StdRng) -> (f64, Vec<f64>, Vec<ExchangeRadical>) {
+
+    mc_fit_with(empirical, sigma, sim.baseline, rads, rng, caso, |newrads| calcola(newrads, fld, sim))
+}
@@ -1,4 +1,89 @@
-use crate::{Radical};
+use crate::{Lineshape, Nucleus, Radical};
+use crate::bessel;
+use crate::complex::Complex;
+use crate::faddeeva;
+use crate::fft;
+use crate::hyperfine::{stick_spectrum_at, LinePositions};
+use crate::linalg;
+use rand::Rng;
+use rand::rngs::StdRng;
+use serde::{Serialize, Deserialize};
+
+// Planck constant over Bohr magneton, in gauss per GHz
+pub(crate) const H_OVER_MUB: f64 = 714.4773;
+// Smallest g-factor a random move may leave: resonance_field divides by it
+pub(crate) const G_MIN: f64 = 0.1;
+
+// Settings shared by every radical of a simulation
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SimOptions {
+    // Microwave frequency [GHz]. When known, each radical sits at hν/(gμB) + dh1;
+    // otherwise at the center of the field axis + dh1, as in ESR Commander.
+    pub mwfq: Option<f64>,
+    // Anything but first order needs B0: without the frequency,
+    // the center of the field axis stands in for hν/(gμB).
+    #[serde(default)]
+    pub positions: LinePositions,
+    #[serde(default)]
+    pub output: OutputMode,
+    // Overmodulation: with an amplitude above zero the spectrum is the
+    // modulated absorption at the given harmonic, and `output` is ignored
+    #[serde(default)]
+    pub modulation: Option<Modulation>,
+    // Fitted along with the spectrum by `mc_fit`
+    #[serde(default)]
+    pub baseline: Baseline,
+}
+
+// Background of the experimental spectrum, solved by linear least squares on every iteration
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Baseline {
+    #[default]
+    None,  // Only the normalization of `errore`
+    Polynomial(usize),  // Order: 0 is an offset, 1 a linear drift, ...
+}
+
+// Normalization and baseline that best match a theoretical spectrum to the experimental one
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BaselineFit {
+    pub norma: f64,
+    // Legendre coefficients, over the points scaled to [-1, 1]:
+    // the first is the mean offset, the second half the drift across the spectrum
+    pub coeffs: Vec<f64>,
+    pub values: Vec<f64>,  // Baseline at every point
+}
+
+// Field modulation of the spectrometer
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Modulation {
+    pub amplitude: f64,  // Peak to peak [G], as in Bruker files
+    #[serde(default = "first_harmonic")]
+    pub harmonic: u32,
+}
+
+fn first_harmonic() -> u32 {
+    1
+}
+
+impl Modulation {
+    pub fn new(amplitude: f64) -> Self {
+        Modulation { amplitude, harmonic: first_harmonic() }
+    }
+}
+
+// What the spectrometer records; ESR Commander only knows the first derivative
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OutputMode {
+    Absorption,  // Rapid scan, field swept echoes
+    #[default]
+    FirstDerivative,
+    SecondDerivative,  // Second harmonic detection
+}
+
+// Resonance field [G] of a radical with g-factor `g` at frequency `mwfq` [GHz]
+pub fn resonance_field(g: f64, mwfq: f64) -> f64 {
+    H_OVER_MUB * mwfq / g
+}
 
 // Calculate theoretical spectra
 pub fn calcola(rads: &Vec<Radical>, sweep: f64, points: f64) -> Vec<f64> {
@@ -8,75 +93,8 @@ pub fn calcola(rads: &Vec<Radical>, sweep: f64, points: f64) -> Vec<f64> {
 
     // Stickspectrum
     for rad in rads {
-        let mut totale = 1.0;  // Total intensity
-        let mut pf = 1.0;  // Max intensity point value
-        let mut pcostanti: Vec<f64> = Vec::new();
-        let mut spini: Vec<f64> = Vec::new();
-
-        for nuc in &rad.nucs {
-            let pcostante = nuc.hpf.val/incrgauss as f64;
-            pcostanti.push(pcostante);
-            spini.push(2.0*nuc.spin.val);
-        }
-
-        let mut pa = 1.0;  // peak area?
-        for (i, nuc) in rad.nucs.iter().enumerate() {
-            pa += pcostanti[i] * spini[i] * nuc.eqs.val;
-        }
-        if pa < points { pa = points; }
-
-        let mut intensity = vec![0.0; pa as usize];
-        intensity[1] = 1.0;  // TODO: check
-
-        for (i, nuc) in rad.nucs.iter().enumerate() {
-            let mut eq = 1;
-            while eq <= nuc.eqs.val as usize {
-                let mut indice1 = pf as usize;
-                while indice1 > 0 {
-                    if intensity[indice1] != 0.0 {
-                        let mut i2 = 1.0;
-                        while i2 <= (2.0*nuc.spin.val) {
-                            let new = indice1 as f64 + i2 * pcostanti[i];
-                            intensity[new as usize]+=intensity[indice1];
-                            totale+=intensity[indice1];
-
-                            i2+=1.0;
-
-                            if new > pf {
-                                pf = new;
-                            }
-                        }  // while i2...
-
-                    }  // if intensity[indice1]...
-
-                    indice1 -= 1; // Decrement
-                }  // while indice1...
-
-                eq+=1;
-            }  // for(eq=1;eq<=nucleis[l][i];i1++)
-        }  // for nuc in rad.nucs
-
-        let shift: isize = ((points as isize)-(pf as isize))/2;
-        let shift_abs: usize = shift.unsigned_abs();  // Eraseme
-
-        // TODO refactor with match
-        if shift < 0 {
-            let mut point = 1;
-            while point < points as usize {
-                intensity[point] = intensity[point-shift_abs];
-                intensity[point-shift_abs] = 0.0;
-
-                point+=1;  // Increment
-            }  // for(i=1;i<=punti;i++)
-        } else if shift > 0 {
-            let mut point = pf as isize;
-            while point as usize >= 1 {
-                intensity[(point as usize)+shift_abs]=intensity[(point as usize)];
-                intensity[(point as usize)]=0.0;
-
-                point-=1;  // Decrement
-            }  // for(i=pf;i>=1;i--)
-        }  // if shift...
+        let (mut intensity, pf, totale) = stickspectrum(&rad.nucs, incrgauss, points);
+        center_sticks(&mut intensity, pf, points);
 
         // ...
         // Stickspectrum is now stored in intensity vector;
@@ -86,56 +104,659 @@ pub fn calcola(rads: &Vec<Radical>, sweep: f64, points: f64) -> Vec<f64> {
         let mut t2 = 2.0/(3.0_f64.sqrt())*rad.lwa.val;  // Lorentzian lineshape
 
         let mut t1 = (-0.02)*(t2.powi(3))*rad.amount.val*rad.lrtz.val /
-            (totale as f64*std::f64::consts::PI);  // Gaussian lineshape
+            (totale*std::f64::consts::PI);  // Gaussian lineshape
 
-        let mut w2 = -(sweep as f64)/2.0;
+        let mut w2 = -sweep/2.0;
 
         let mut point = 1;
         while point < points as usize {
             let a = w2-rad.dh1.val;
             // Peak intensity!
             lno[point] = (t1*a)/((1.0+t2.powi(2)*a.powi(2))*(1.0+t2.powi(2)*a.powi(2)));
-            w2 += incrgauss as f64;
+            w2 += incrgauss;
 
             point+=1;  // Increment point
         }  // for (j=1;j<=punti;j++)
 
-        w2 = -(sweep as f64)/2.0; // reset w2
+        w2 = -sweep/2.0; // reset w2
         t2 = 2.0/rad.lwa.val;  // change t2
 
         t1 = -rad.amount.val*(t2.powi(3))*0.01*(100.0-rad.lrtz.val)/
-            (totale as f64*(2.0*std::f64::consts::PI).sqrt());  // 100-lorentz == gauss
+            (totale*(2.0*std::f64::consts::PI).sqrt());  // 100-lorentz == gauss
 
         let mut point = 1;
         while point < points as usize {
             let a = w2-rad.dh1.val;
             let dd = (std::f64::consts::E).powf(-0.5*(t2.powi(2))*(a.powi(2)));
             if dd > 1E-35 { lno[point] += t1*a*dd; }
-            w2 += incrgauss as f64;
+            w2 += incrgauss;
 
             point+=1;  // Increment point
         }  // for (j=1;j<=punti;j++)
 
-        point = 1;  // Restart counter
-        while point < points as usize {
-            if intensity[point] != 0.0 {
-                let mut i1 = 1;
-                while i1 < points as usize {
-                    let i2: isize = (points as isize/2) - i1 as isize;
-                    if ((point as isize -i2) >= 1) && ((point as isize -i2) < (points as isize)) {
-                        newteor[(point as isize -i2 as isize) as usize]+=(lno[i1] as f64)*(intensity[point] as f64);
+        convolve_sticks(&intensity, &lno, &mut newteor);
+    }
+
+    newteor  // return
+}  // fn calcola
+
+// Stick spectrum on the grid of `calcola`, from index 1, and its max point and total intensity
+fn stickspectrum(nucs: &[Nucleus], incrgauss: f64, points: f64) -> (Vec<f64>, f64, f64) {
+    let mut totale = 1.0;  // Total intensity
+    let mut pf = 1.0;  // Max intensity point value
+
+    let mut intensity = vec![0.0; stick_points(nucs, incrgauss, points)];
+    intensity[1] = 1.0;  // TODO: check
+
+    for nuc in nucs {
+        add_nucleus(&mut intensity, &mut pf, &mut totale, nuc, incrgauss);
+    }
+
+    (intensity, pf, totale)
+}
+
+// Room for the sticks, before centering them
+fn stick_points(nucs: &[Nucleus], incrgauss: f64, points: f64) -> usize {
+    let mut pa = 1.0;  // peak area?
+    for nuc in nucs {
+        let pcostante = nuc.hpf.val/incrgauss;
+        pa += pcostante * (2.0*nuc.spin.val) * nuc.eqs.val;
+    }
+    if pa < points { pa = points; }
+    pa as usize
+}
+
+// Split every stick of `intensity` in the lines of a group of equivalent nuclei
+fn add_nucleus(intensity: &mut [f64], pf: &mut f64, totale: &mut f64, nuc: &Nucleus, incrgauss: f64) {
+    let pcostante = nuc.hpf.val/incrgauss;
+
+    let mut eq = 1;
+    while eq <= nuc.eqs.val as usize {
+        let mut indice1 = *pf as usize;
+        while indice1 > 0 {
+            if intensity[indice1] != 0.0 {
+                let mut i2 = 1.0;
+                while i2 <= (2.0*nuc.spin.val) {
+                    let new = indice1 as f64 + i2 * pcostante;
+                    intensity[new as usize]+=intensity[indice1];
+                    *totale+=intensity[indice1];
+
+                    i2+=1.0;
+
+                    if new > *pf {
+                        *pf = new;
                     }
+                }  // while i2...
 
-                    i1+=1;  // Increment 1i
-                }  // for (i1=1;i1<=punti;i1++)
-            }  // if intensity[point]
+            }  // if intensity[indice1]...
 
-            point+=1; // Increment j
-        }  // for (j=1;j<=punti;j++)
+            indice1 -= 1; // Decrement
+        }  // while indice1...
+
+        eq+=1;
+    }  // for(eq=1;eq<=nucleis[l][i];i1++)
+}
+
+// Center the stick spectrum on the sweep
+fn center_sticks(intensity: &mut [f64], pf: f64, points: f64) {
+    let shift: isize = ((points as isize)-(pf as isize))/2;
+    let shift_abs: usize = shift.unsigned_abs();  // Eraseme
+
+    // TODO refactor with match
+    if shift < 0 {
+        let mut point = 1;
+        while point < points as usize {
+            intensity[point] = intensity[point-shift_abs];
+            intensity[point-shift_abs] = 0.0;
+
+            point+=1;  // Increment
+        }  // for(i=1;i<=punti;i++)
+    } else if shift > 0 {
+        let mut point = pf as isize;
+        while point as usize >= 1 {
+            intensity[(point as usize)+shift_abs]=intensity[point as usize];
+            intensity[point as usize]=0.0;
+
+            point-=1;  // Decrement
+        }  // for(i=pf;i>=1;i--)
+    }  // if shift...
+}
+
+// Sticks of `calcola`, one array per M_I of the first group, with that M_I.
+// Same grid and the same sum as `stickspectrum`, so the lines don't move when B or C leave 0.
+fn stickspectrum_by_m(nucs: &[Nucleus], incrgauss: f64, points: f64) -> (Vec<(f64, Vec<f64>)>, f64, f64) {
+    let (first, rest) = match nucs.split_first() {
+        Some(split) => split,
+        None => {
+            let (intensity, pf, totale) = stickspectrum(nucs, incrgauss, points);
+            return (vec![(0.0, intensity)], pf, totale);
+        }
+    };
+
+    let len = stick_points(nucs, incrgauss, points);
+    let mut start = vec![0.0; len];
+    start[1] = 1.0;
+
+    // k counts the steps of 2I of every nucleus of the first group, from its lowest field line
+    let steps = (2.0*first.spin.val).floor().max(0.0) as usize;
+    let eqs = first.eqs.val.max(0.0) as usize;
+    let pcostante = first.hpf.val/incrgauss;
+    let mut by_k = vec![start];
+    for _ in 0..eqs {
+        let mut next = vec![vec![0.0; len]; by_k.len() + steps];
+        for (k, intensity) in by_k.iter().enumerate() {
+            for (point, value) in intensity.iter().enumerate().filter(|(_, v)| **v != 0.0) {
+                next[k][point] += value;
+                for i2 in 1..=steps {
+                    next[k + i2][(point as f64 + i2 as f64 * pcostante) as usize] += value;
+                }
+            }
+        }
+        by_k = next;
     }
 
-    newteor  // return
-}  // fn calcola
+    let mut pf = 1.0;
+    let mut totale = 0.0;
+    for intensity in by_k.iter_mut() {
+        let mut pf_k = intensity.iter().rposition(|v| *v != 0.0).unwrap_or(1) as f64;
+        let mut totale_k = 0.0;
+        for nuc in rest {
+            add_nucleus(intensity, &mut pf_k, &mut totale_k, nuc, incrgauss);
+        }
+        pf = f64::max(pf, pf_k);
+        totale += intensity.iter().sum::<f64>();
+    }
+
+    let spin = first.spin.val;
+    let lines = by_k.into_iter().enumerate()
+        .map(|(k, intensity)| (eqs as f64 * spin - k as f64, intensity))
+        .collect();
+    (lines, pf, totale)
+}
+
+// `calcola` with every option of a radical: lineshape, phase, M_I-dependent widths
+// and output mode. Lines sit exactly where `calcola` puts them.
+fn calcola_lines(rads: &[Radical], sweep: f64, points: usize, output: OutputMode) -> Vec<f64> {
+    let n = points as f64;
+    let incrgauss = sweep/(n - 1.0);
+    let mut lno = vec![0.0; points];
+    let mut newteor = vec![0.0; points];
+
+    for rad in rads {
+        let (mut lines, pf, totale) = if rad.lwb.val == 0.0 && rad.lwc.val == 0.0 {
+            let (intensity, pf, totale) = stickspectrum(&rad.nucs, incrgauss, n);
+            (vec![(0.0, intensity)], pf, totale)
+        } else {
+            stickspectrum_by_m(&rad.nucs, incrgauss, n)
+        };
+
+        for (m, intensity) in lines.iter_mut() {
+            // A + B·M + C·M² can go below zero for outer lines: nothing to draw
+            let lw = linewidth(rad, &[*m]);
+            if lw <= 0.0 {
+                continue;
+            }
+
+            center_sticks(intensity, pf, n);
+            let shape = Shape::of(rad, lw, totale);
+            let mut w2 = -sweep/2.0;
+            for value in lno.iter_mut().skip(1) {
+                *value = shape.at(w2 - rad.dh1.val, output);
+                w2 += incrgauss;
+            }
+            convolve_sticks(intensity, &lno, &mut newteor);
+        }
+    }
+
+    newteor
+}
+
+// Width of a line: A + B·M + C·M², with M the M_I of the first nucleus group
+pub(crate) fn linewidth(rad: &Radical, m_i: &[f64]) -> f64 {
+    let m = m_i.first().copied().unwrap_or(0.0);
+    rad.lwa.val + rad.lwb.val * m + rad.lwc.val * m * m
+}
+
+// Lorentzian and Gaussian parts of a line.
+// The pseudo-Voigt has the constants of `calcola`: its first derivative is
+// exactly the ESR Commander one, and the absorption is that of lines with area
+// amount·lrtz% and amount·(100-lrtz)%, over totale.
+// `calcola` scales its Lorentzian with lwa instead of 1/lwa: keep that shape
+// at lwa and stretch it by lw/lwa, so broader lines are broader in both parts.
+// The Voigt has area amount/totale and peak to peak widths lw (Gaussian)
+// and lwl·lw/lwa (Lorentzian).
+enum Profile {
+    PseudoVoigt {
+        tl: f64,  // Inverse Lorentzian half width
+        tg: f64,  // Inverse Gaussian standard deviation
+        area_l: f64,
+        area_g: f64,
+    },
+    Voigt {
+        sigma: f64,  // Gaussian standard deviation
+        gamma: f64,  // Lorentzian half width
+        area: f64,
+    },
+}
+
+// A profile seen with a detection phase: cos φ absorption + sin φ dispersion
+pub(crate) struct Shape {
+    profile: Profile,
+    cos: f64,
+    sin: f64,
+}
+
+impl Shape {
+    pub(crate) fn new(lwa: f64, lrtz: f64, amount: f64, lw: f64, totale: f64) -> Self {
+        Shape {
+            profile: Profile::PseudoVoigt {
+                tl: 2.0/(3.0_f64.sqrt())*lwa*lwa/lw,
+                tg: 2.0/lw,
+                area_l: 0.01*amount*lrtz/totale,
+                area_g: 0.01*amount*(100.0-lrtz)/totale,
+            },
+            cos: 1.0,
+            sin: 0.0,
+        }
+    }
+
+    // Shape of a line lw wide of this radical
+    pub(crate) fn of(rad: &Radical, lw: f64, totale: f64) -> Self {
+        let shape = match rad.lineshape {
+            Lineshape::PseudoVoigt => Shape::new(rad.lwa.val, rad.lrtz.val, rad.amount.val, lw, totale),
+            Lineshape::Voigt => Shape {
+                profile: Profile::Voigt {
+                    sigma: lw/2.0,
+                    gamma: 3.0_f64.sqrt()/2.0*rad.lwl.val*lw/rad.lwa.val,
+                    area: rad.amount.val/totale,
+                },
+                cos: 1.0,
+                sin: 0.0,
+            },
+        };
+        shape.phased(rad.phase.val)
+    }
+
+    // Phase [deg]; exactly in phase at 0
+    pub(crate) fn phased(self, phase: f64) -> Self {
+        if phase == 0.0 {
+            return self;
+        }
+        let (sin, cos) = phase.to_radians().sin_cos();
+        Shape { cos, sin, ..self }
+    }
+
+    // Value at distance `a` [G] from the line center
+    pub(crate) fn at(&self, a: f64, output: OutputMode) -> f64 {
+        match self.profile {
+            Profile::PseudoVoigt { tl, tg, area_l, area_g } => {
+                let absorption = pseudo_voigt(tl, tg, area_l, area_g, a, output);
+                if self.sin == 0.0 {
+                    return absorption;
+                }
+                self.cos*absorption + self.sin*pseudo_voigt_dispersion(tl, tg, area_l, area_g, a, output)
+            }
+            Profile::Voigt { sigma, gamma, area } => {
+                let v = voigt(sigma, gamma, area, a, output);
+                self.cos*v.re + self.sin*v.im
+            }
+        }
+    }
+}
+
+// k w(z), z = (a + iγ)/(σ√2), k = area/(σ√2π), and its derivatives through
+// w' = -2zw + 2i/√π: the real part is the absorption, the imaginary one the dispersion
+fn voigt(sigma: f64, gamma: f64, area: f64, a: f64, output: OutputMode) -> Complex {
+    if sigma <= 0.0 {
+        // Pure Lorentzian, area/π/(γ - ia); w has no limit here
+        let k = Complex::new(area/std::f64::consts::PI, 0.0);
+        let c = Complex::new(gamma, -a);
+        return match output {
+            OutputMode::Absorption => k/c,
+            OutputMode::FirstDerivative => k*Complex::I/(c*c),
+            OutputMode::SecondDerivative => -k.scale(2.0)/(c*c*c),
+        };
+    }
+
+    let s2 = sigma*std::f64::consts::SQRT_2;
+    let z = Complex::new(a/s2, gamma/s2);
+    let w = faddeeva::w(z);
+    let k = area/(sigma*(2.0*std::f64::consts::PI).sqrt());
+
+    match output {
+        OutputMode::Absorption => w.scale(k),
+        OutputMode::FirstDerivative => faddeeva::dw(z, w).scale(k/s2),
+        OutputMode::SecondDerivative => {
+            // w'' = -2w - 2zw'
+            let dw = faddeeva::dw(z, w);
+            (-(w + z*dw).scale(2.0)).scale(k/(s2*s2))
+        }
+    }
+}
+
+// The Lorentzian is the limit of the Voigt with no Gaussian, the Gaussian the one with no Lorentzian;
+// the imaginary part of w along the real axis is Dawson's function
+fn pseudo_voigt_dispersion(tl: f64, tg: f64, area_l: f64, area_g: f64, a: f64, output: OutputMode) -> f64 {
+    let lorentz = if tl > 0.0 { voigt(0.0, 1.0/tl, area_l, a, output).im } else { 0.0 };
+    let gauss = if tg > 0.0 { voigt(1.0/tg, 0.0, area_g, a, output).im } else { 0.0 };
+    lorentz + gauss
+}
+
+fn pseudo_voigt(tl: f64, tg: f64, area_l: f64, area_g: f64, a: f64, output: OutputMode) -> f64 {
+    let u = (tl*a).powi(2);
+    let lorentz = area_l*tl/std::f64::consts::PI;
+    let dd = (-0.5*(tg*a).powi(2)).exp();
+    let gauss = if dd > 1E-35 {
+        area_g*tg/(2.0*std::f64::consts::PI).sqrt()*dd
+    } else {
+        0.0
+    };
+
+    let t2l = tl.powi(2);
+    let t2g = tg.powi(2);
+    match output {
+        OutputMode::Absorption => lorentz/(1.0+u) + gauss,
+        OutputMode::FirstDerivative => -2.0*lorentz*t2l*a/(1.0+u).powi(2) - gauss*t2g*a,
+        OutputMode::SecondDerivative => -2.0*lorentz*t2l*(1.0-3.0*u)/(1.0+u).powi(3)
+            + gauss*t2g*(t2g*a*a-1.0),
+    }
+}
+
+// Put the lineshape `lno` (centered at points/2) on every stick of `intensity`:
+// newteor[k] += Σ intensity[j]·lno[k - j + points/2], all indices within 1..points.
+// The direct sum costs sticks·points, the FFT about points·log(points):
+// pick the cheaper, the results agree to round-off.
+pub(crate) fn convolve_sticks(intensity: &[f64], lno: &[f64], newteor: &mut [f64]) {
+    let points = newteor.len();
+    let sticks = intensity.iter().take(points).skip(1).filter(|i| **i != 0.0).count();
+    let n = (2 * points).next_power_of_two();
+    let fft_cost = FFT_COST * n * (n.trailing_zeros() as usize);
+
+    if sticks * points > fft_cost {
+        convolve_sticks_fft(intensity, lno, newteor);
+    } else {
+        convolve_sticks_direct(intensity, lno, newteor);
+    }
+}
+
+// Time of an FFT step against a multiply-add of the direct sum, measured by
+// `cargo test --release convolution_timings -- --ignored --nocapture`:
+// the two break even around 200 sticks at 4096 and 8192 points
+pub(crate) const FFT_COST: usize = 8;
+
+// The original nested loop
+pub(crate) fn convolve_sticks_direct(intensity: &[f64], lno: &[f64], newteor: &mut [f64]) {
+    let points = newteor.len();
+    let mut point = 1;
+    while point < points {
+        if intensity[point] != 0.0 {
+            let mut i1 = 1;
+            while i1 < points {
+                let i2: isize = (points as isize/2) - i1 as isize;
+                if ((point as isize -i2) >= 1) && ((point as isize -i2) < (points as isize)) {
+                    newteor[(point as isize -i2) as usize]+=lno[i1]*intensity[point];
+                }
+
+                i1+=1;  // Increment 1i
+            }  // for (i1=1;i1<=punti;i1++)
+        }  // if intensity[point]
+
+        point+=1; // Increment j
+    }  // for (j=1;j<=punti;j++)
+}
+
+pub(crate) fn convolve_sticks_fft(intensity: &[f64], lno: &[f64], newteor: &mut [f64]) {
+    let points = newteor.len();
+    if points < 2 {
+        return;
+    }
+
+    // Index 0 is never used by `calcola`
+    let mut sticks = intensity[..points].to_vec();
+    let mut shape = lno[..points].to_vec();
+    sticks[0] = 0.0;
+    shape[0] = 0.0;
+
+    let conv = fft::convolve(&sticks, &shape);
+    let half = points / 2;
+    for (k, y) in newteor.iter_mut().enumerate().skip(1) {
+        *y += conv[k + half];
+    }
+}
+
+// Widest oversampling of the grid of `binned`
+const MAX_OVERSAMPLING: usize = 16;
+
+// Lines (field, intensity) of one shape, lw wide, on a uniform grid of `points` from `lo`, `step` apart.
+// Every line is split between its two closest points of a grid fine enough for lw,
+// convolved with the shape by `convolve_sticks`, then the fine grid is read back every few points.
+pub(crate) fn binned(lines: &[(f64, f64)], shape: &Shape, lw: f64, lo: f64, step: f64, points: usize, output: OutputMode) -> Vec<f64> {
+    let over = ((10.0 * step / lw).ceil() as usize).clamp(1, MAX_OVERSAMPLING);
+    let h = step / over as f64;
+    // Index 0 is never used by `convolve_sticks`
+    let fine = (points - 1) * over + 2;
+    let last = (fine - 1) as f64;
+
+    let mut intensity = vec![0.0; fine];
+    for (field, int) in lines {
+        let pos = (field - lo) / h + 1.0;
+        let i = pos.floor();
+        let frac = pos - i;
+        if i >= 1.0 && i <= last {
+            intensity[i as usize] += int * (1.0 - frac);
+        }
+        if i >= 0.0 && i + 1.0 <= last {
+            intensity[i as usize + 1] += int * frac;
+        }
+    }
+
+    let half = (fine / 2) as f64;
+    let lno: Vec<f64> = (0..fine).map(|p| shape.at((p as f64 - half) * h, output)).collect();
+
+    let mut spectrum = vec![0.0; fine];
+    convolve_sticks(&intensity, &lno, &mut spectrum);
+    (0..points).map(|point| spectrum[1 + point * over]).collect()
+}
+
+// Lines anywhere on a uniform grid, by `binned`: smooth in the line positions.
+// First order radicals sit where `calcola` centers them,
+// so fits on this spectrum and on `calcola_fld` agree; the others at the center of the axis.
+fn calcola_binned(rads: &[Radical], lo: f64, step: f64, points: usize, opts: &SimOptions) -> Vec<f64> {
+    let hi = lo + step * (points - 1) as f64;
+    let center = match opts.positions {
+        // Center of the stick spectrum of `calcola`, up to its rounding
+        LinePositions::FirstOrder => lo + step * (points.div_ceil(2) + 1) as f64,
+        _ => (lo + hi) / 2.0,
+    };
+    let mut newteor = vec![0.0; points];
+
+    for rad in rads {
+        let b0 = match opts.mwfq {
+            Some(mwfq) => resonance_field(rad.g.val, mwfq) - (lo + hi) / 2.0,
+            None => 0.0,
+        } + center + rad.dh1.val;
+
+        let lines = stick_spectrum_at(rad, b0, opts.positions);
+        let totale: f64 = lines.iter().map(|l| l.intensity).sum();
+
+        // Lines of the same width share their shape.
+        // A + B·M + C·M² can go below zero for outer lines: nothing to draw
+        let mut widths: Vec<(f64, Vec<(f64, f64)>)> = Vec::new();
+        for line in &lines {
+            let lw = linewidth(rad, &line.m_i);
+            if lw <= 0.0 {
+                continue;
+            }
+            match widths.iter_mut().find(|(w, _)| *w == lw) {
+                Some((_, group)) => group.push((line.field, line.intensity)),
+                None => widths.push((lw, vec![(line.field, line.intensity)])),
+            }
+        }
+
+        for (lw, group) in widths {
+            let shape = Shape::of(rad, lw, totale);
+            let spectrum = binned(&group, &shape, lw, lo, step, points, opts.output);
+            for (y, v) in newteor.iter_mut().zip(spectrum) {
+                *y += v;
+            }
+        }
+    }
+
+    newteor
+}
+
+// Linear interpolation of a uniform grid (start, step) at field x
+pub(crate) fn interpolate(grid: &[f64], start: f64, step: f64, x: f64) -> f64 {
+    let pos = (x - start) / step;
+    if pos <= 0.0 {
+        return grid[0];
+    }
+
+    let i = pos.floor() as usize;
+    if i + 1 >= grid.len() {
+        return grid[grid.len() - 1];
+    }
+
+    let frac = pos - i as f64;
+    grid[i] * (1.0 - frac) + grid[i + 1] * frac
+}
+
+// Pseudo-modulation (Hyde et al., J. Magn. Reson. 1990, 87, 63): the harmonic
+// of an absorption sampled every `step` gauss, through its Fourier transform
+// times iⁿ Jn(κ Bm/2). Scaled by n!(4/Bm)ⁿ, so that with small amplitudes
+// it becomes the n-th derivative; harmonic 0 is the averaged absorption.
+// Points within Bm/2 of the ends see the other end of the spectrum.
+pub fn modulate(absorption: &[f64], step: f64, modulation: &Modulation) -> Vec<f64> {
+    let len = absorption.len();
+    let half = modulation.amplitude / 2.0;
+    if len < 2 || step <= 0.0 || half <= 0.0 {
+        return absorption.to_vec();
+    }
+
+    let n = len.next_power_of_two();
+    let mut data: Vec<Complex> = absorption.iter().map(|y| Complex::new(*y, 0.0)).collect();
+    data.resize(n, Complex::default());
+    fft::fft(&mut data, false);
+
+    let harmonic = modulation.harmonic;
+    let factorial: f64 = (1..=harmonic).map(|k| k as f64).product();
+    let scale = factorial * (2.0 / half).powi(harmonic as i32);
+    // iⁿ
+    let phase = [Complex::new(1.0, 0.0), Complex::I, Complex::new(-1.0, 0.0), -Complex::I][harmonic as usize % 4];
+
+    for (k, y) in data.iter_mut().enumerate() {
+        // Frequencies above n/2 are the negative ones
+        let k = if k <= n / 2 { k as f64 } else { k as f64 - n as f64 };
+        let kappa = 2.0 * std::f64::consts::PI * k / (n as f64 * step);
+        *y = *y * phase * (scale * bessel::jn(harmonic, kappa * half));
+    }
+
+    fft::fft(&mut data, true);
+    data.into_iter().take(len).map(|c| c.re).collect()
+}
+
+// Absorption of `simulate` on a uniform grid reaching Bm/2 past both ends of `fld`,
+// modulated, then read back on `fld`
+pub(crate) fn modulated<S>(fld: &[f64], modulation: &Modulation, simulate: S) -> Vec<f64>
+    where S: Fn(&[f64]) -> Vec<f64> {
+
+    let points = fld.len();
+    let lo = fld.iter().cloned().fold(f64::INFINITY, f64::min);
+    let hi = fld.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let step = (hi - lo) / (points - 1) as f64;
+    if step <= 0.0 {
+        return vec![0.0; points];
+    }
+
+    let margin = (modulation.amplitude / 2.0 / step).ceil() as usize + 2;
+    let start = lo - margin as f64 * step;
+    let grid: Vec<f64> = (0..points + 2 * margin).map(|i| start + step * i as f64).collect();
+
+    let spectrum = modulate(&simulate(&grid), step, modulation);
+    fld.iter().map(|x| interpolate(&spectrum, start, step, *x)).collect()
+}
+
+// Constant step of an ascending field axis, if it has one
+pub(crate) fn uniform_step(fld: &[f64]) -> Option<f64> {
+    let n = fld.len();
+    if n < 2 {
+        return None;
+    }
+
+    let step = (fld[n - 1] - fld[0]) / (n - 1) as f64;
+    let uniform = step > 0.0 && fld.iter().enumerate()
+        .all(|(i, x)| (x - (fld[0] + step * i as f64)).abs() <= 1e-6 * step);
+
+    if uniform { Some(step) } else { None }
+}
+
+// Calculate theoretical spectra on an experimental field axis [G].
+// Sweep and points come from the axis; see `SimOptions` for where radicals sit.
+// First order lines sit on the grid of `calcola`, whatever their shape: a uniform
+// ascending axis is that grid, any other axis is simulated on a uniform grid over
+// the same range, then interpolated. With first derivative output and a single in phase
+// pseudo-Voigt linewidth, that's exactly the output of `calcola`.
+// Other line positions are binned on the grid, see `calcola_binned`.
+pub fn calcola_fld(rads: &[Radical], fld: &[f64], opts: &SimOptions) -> Vec<f64> {
+    simulate(rads, fld, opts, true)
+}
+
+// Positions aren't snapped to the grid as in `calcola`,
+// so the spectrum is smooth in every parameter, as finite differences need
+pub(crate) fn calcola_smooth(rads: &[Radical], fld: &[f64], opts: &SimOptions) -> Vec<f64> {
+    simulate(rads, fld, opts, false)
+}
+
+fn simulate(rads: &[Radical], fld: &[f64], opts: &SimOptions, on_grid: bool) -> Vec<f64> {
+    let points = fld.len();
+    if points < 2 {
+        return vec![0.0; points];
+    }
+
+    if let Some(modulation) = opts.modulation.as_ref().filter(|m| m.amplitude > 0.0) {
+        let absorption = SimOptions { output: OutputMode::Absorption, modulation: None, ..opts.clone() };
+        return modulated(fld, modulation, |grid| simulate(rads, grid, &absorption, on_grid));
+    }
+
+    let lo = fld.iter().cloned().fold(f64::INFINITY, f64::min);
+    let hi = fld.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+
+    if !on_grid || opts.positions != LinePositions::FirstOrder {
+        if let Some(step) = uniform_step(fld) {
+            return calcola_binned(rads, lo, step, points, opts);
+        }
+        let step = (hi - lo) / (points - 1) as f64;
+        let grid = calcola_binned(rads, lo, step, points, opts);
+        return fld.iter().map(|x| interpolate(&grid, lo, step, *x)).collect();
+    }
+
+    // `calcola` only knows offsets from the center of the sweep
+    let rads: Vec<Radical> = match opts.mwfq {
+        Some(mwfq) => rads.iter().cloned().map(|mut rad| {
+            rad.dh1.val += resonance_field(rad.g.val, mwfq) - (lo + hi) / 2.0;
+            rad
+        }).collect(),
+        None => rads.to_vec(),
+    };
+
+    let per_line = rads.iter().any(|rad| {
+        rad.lwb.val != 0.0 || rad.lwc.val != 0.0 || rad.lineshape != Lineshape::PseudoVoigt || rad.phase.val != 0.0
+    });
+    let grid = |sweep: f64| if per_line || opts.output != OutputMode::FirstDerivative {
+        calcola_lines(&rads, sweep, points, opts.output)
+    } else {
+        calcola(&rads, sweep, points as f64)
+    };
+
+    if let Some(step) = uniform_step(fld) {
+        return grid(step * (points - 1) as f64);
+    }
+
+    let grid = grid(hi - lo);
+    let step = (hi - lo) / (points - 1) as f64;
+
+    fld.iter().map(|x| interpolate(&grid, lo, step, *x)).collect()
+}
 
 // MONTECARLO
 
@@ -155,11 +776,10 @@ pub fn errore(
         somma2 += exp[j].abs() * newteor[j].abs();
     }
 
-    let norma: f64;
-    if somma1 != 0.0 {
-        norma = somma2/somma1
+    let norma = if somma1 != 0.0 {
+        somma2/somma1
     } else {
-        norma = 0.0
+        0.0
     };
 
     for j in start..fine {
@@ -173,30 +793,101 @@ pub fn errore(
     (newsigma, newteor)
 }  // mc
 
+// Legendre polynomials up to `order` at every point, with the points scaled to [-1, 1]
+fn legendre(points: usize, order: usize) -> Vec<Vec<f64>> {
+    let mut basis = vec![vec![1.0; points]];
+    if order == 0 {
+        return basis;
+    }
+
+    let x: Vec<f64> = (0..points).map(|i| 2.0 * i as f64 / (points - 1).max(1) as f64 - 1.0).collect();
+    basis.push(x.clone());
+    for k in 1..order {
+        let next = (0..points).map(|i| {
+            ((2 * k + 1) as f64 * x[i] * basis[k][i] - k as f64 * basis[k - 1][i]) / (k + 1) as f64
+        }).collect();
+        basis.push(next);
+    }
+    basis
+}
+
+// Least squares exp ≈ norma·teor + Σ c_k P_k, on the points compared by `errore`.
+// None if the system is singular, e.g. a flat theoretical spectrum with an offset.
+pub fn fit_baseline(exp: &[f64], teor: &[f64], order: usize) -> Option<BaselineFit> {
+    let points = teor.len().min(exp.len());
+    if points < order + 3 {
+        return None;
+    }
+
+    let mut columns = vec![teor[..points].to_vec()];
+    columns.extend(legendre(points, order));
+
+    // Normal equations
+    let n = columns.len();
+    let mut a = vec![vec![0.0; n]; n];
+    let mut b = vec![0.0; n];
+    for i in 0..n {
+        for j in i..n {
+            a[i][j] = (1..points).map(|p| columns[i][p] * columns[j][p]).sum();
+            a[j][i] = a[i][j];
+        }
+        b[i] = (1..points).map(|p| columns[i][p] * exp[p]).sum();
+    }
+
+    let x = linalg::solve(a, b)?;
+    let values = (0..points).map(|p| (1..n).map(|k| x[k] * columns[k][p]).sum()).collect();
+    Some(BaselineFit { norma: x[0], coeffs: x[1..].to_vec(), values })
+}
+
+// `errore` with a baseline: the returned spectrum is norma·teor + baseline
+pub fn errore_with(exp: &[f64], newteor: Vec<f64>, baseline: Baseline) -> (f64, Vec<f64>) {
+    let fit = match baseline {
+        Baseline::None => None,
+        Baseline::Polynomial(order) => fit_baseline(exp, &newteor, order),
+    };
+
+    match fit {
+        Some(fit) => {
+            let fitted: Vec<f64> = newteor.iter().zip(fit.values.iter()).map(|(y, b)| fit.norma * y + b).collect();
+            let fine = fitted.len();
+            let somma: f64 = (1..fine).map(|j| (exp[j] - fitted[j]).powi(2)).sum();
+            ((somma / (fine - 1) as f64).sqrt(), fitted)
+        }
+        None => errore(exp, newteor.len() as f64, newteor),
+    }
+}
+
 // TODO use better var names
 // Temporarily maintaining legacy name to make easier comparison
 
 // Reset potentially aberrant value returned by MC function;
-fn check_pars(mut rad: Radical) -> Radical {
+pub(crate) fn check_pars(mut rad: Radical) -> Radical {
     if rad.lwa.val < 0.0 { rad.lwa.val = 0.0 };
     if rad.lrtz.val < 0.0 { rad.lrtz.val = 0.0 };
+    if rad.lwl.val < 0.0 { rad.lwl.val = 0.0 };
     if rad.amount.val < 0.0 { rad.amount.val = 0.0 };
     if rad.lrtz.val > 100.0 { rad.lrtz.val = 100.0 };
+    if rad.g.val < G_MIN { rad.g.val = G_MIN };
     rad
 }
 
-fn caso(rads: &[Radical]) -> Vec<Radical> {
+fn caso(rads: &[Radical], rng: &mut StdRng) -> Vec<Radical> {
     let mut mc_rads = Vec::new();
 
     for mut rad in rads.iter().cloned() {
-        rad.lwa = rad.lwa.randomize();
-        rad.amount = rad.amount.randomize();
-        rad.lrtz = rad.lrtz.randomize();
-        rad.dh1 = rad.dh1.randomize();
+        rad.lwa = rad.lwa.randomize(rng);
+        rad.lwb = rad.lwb.randomize(rng);
+        rad.lwc = rad.lwc.randomize(rng);
+        rad.amount = rad.amount.randomize(rng);
+        rad.lrtz = rad.lrtz.randomize(rng);
+        rad.lwl = rad.lwl.randomize(rng);
+        rad.phase = rad.phase.randomize(rng);
+        rad.dh1 = rad.dh1.randomize(rng);
+        rad.g = rad.g.randomize(rng);
 
         let mut randomized_nucs = Vec::new();
         for mut nuc in rad.nucs.iter().cloned() {
-            nuc.hpf = nuc.hpf.randomize();
+            nuc.hpf = nuc.hpf.randomize(rng);
             randomized_nucs.push(nuc);
         }
 
@@ -211,21 +902,38 @@ fn caso(rads: &[Radical]) -> Vec<Radical> {
 
 // This is my way to wrap MC logic in a single function
 // This return a simple tuple, so we maintain pure functional paradigm
+// The field axis is the experimental one, so `fld` and `empirical` have the same length
 pub fn mc_fit(
     empirical: &[f64],
-    points: f64,
-    sweep: f64,
-    mut sigma: f64,
-    mut rads: Vec<Radical>) -> (f64, Vec<f64>, Vec<Radical>) {
+    fld: &[f64],
+    opts: &SimOptions,
+    sigma: f64,
+    rads: Vec<Radical>,
+    rng: &mut StdRng) -> (f64, Vec<f64>, Vec<Radical>) {
 
+    mc_fit_with(empirical, sigma, opts.baseline, rads, rng, caso, |newrads| calcola_fld(newrads, fld, opts))
+}
+
+// Same MC step for any kind of radical: `randomize` moves the parameters with `rng`,
+// `simulate` gives the theoretical spectrum on the experimental axis
+pub fn mc_fit_with<R, F, S>(
+    empirical: &[f64],
+    mut sigma: f64,
+    baseline: Baseline,
+    mut rads: Vec<R>,
+    rng: &mut StdRng,
+    randomize: F,
+    simulate: S) -> (f64, Vec<f64>, Vec<R>)
+where
+    F: Fn(&[R], &mut StdRng) -> Vec<R>,
+    S: Fn(&[R]) -> Vec<f64>,
+{
     // Randomize parameters for next iteration
-    let newrads = caso(&rads);
+    let newrads = randomize(&rads, rng);
 
     // Reallocate params if variance is less than previous iteration
-    let (newsigma, newteor) = errore(
-        empirical, points,
-        calcola(&newrads, sweep, points)
-    );
+    let newteor = simulate(&newrads);
+    let (newsigma, newteor) = errore_with(empirical, newteor, baseline);
 
     if newsigma < sigma {
         sigma = newsigma;
@@ -235,3 +943,115 @@ pub fn mc_fit(
     // Return newteor nevertheless: you plot it anywhere
     (sigma, newteor, rads)
 }
+
+// SIMULATED ANNEALING
+
+// Temperature schedule of `anneal`. Temperatures are relative: a step that makes sigma
+// worse by a fraction T is accepted with probability 1/e.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Annealing {
+    pub temperature: f64,  // Initial temperature
+    pub cooling: f64,  // The temperature is multiplied by this after every step
+    pub reheat_after: usize,  // Steps without a new best before reheating; 0 never reheats
+    pub reheats: usize,  // How many times; then it cools down to a plain `mc_fit`
+}
+
+impl Default for Annealing {
+    fn default() -> Self {
+        Annealing { temperature: 0.05, cooling: 0.995, reheat_after: 500, reheats: 3 }
+    }
+}
+
+// Where the annealing is: the walker can move uphill, the best is kept apart
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AnnealState<R> {
+    pub rads: Vec<R>,  // Walker
+    pub sigma: f64,
+    pub best: Vec<R>,
+    pub best_sigma: f64,
+    pub temperature: f64,
+    pub stall: usize,  // Steps since the last new best
+    pub reheated: usize,
+}
+
+impl<R: Clone> AnnealState<R> {
+    pub fn new(rads: Vec<R>, sigma: f64, schedule: &Annealing) -> Self {
+        AnnealState {
+            rads: rads.clone(),
+            sigma,
+            best: rads,
+            best_sigma: sigma,
+            temperature: schedule.temperature,
+            stall: 0,
+            reheated: 0,
+        }
+    }
+}
+
+// Metropolis criterion, on the relative change of sigma
+fn metropolis(sigma: f64, newsigma: f64, temperature: f64, rng: &mut StdRng) -> bool {
+    if newsigma < sigma {
+        return true;
+    }
+    if temperature <= 0.0 || !newsigma.is_finite() {
+        return false;
+    }
+    let worse = (newsigma - sigma) / sigma;
+    rng.gen::<f64>() < (-worse / temperature).exp()
+}
+
+// One annealing step with the same parameter moves as `mc_fit`
+pub fn anneal(
+    empirical: &[f64],
+    fld: &[f64],
+    opts: &SimOptions,
+    schedule: &Annealing,
+    state: AnnealState<Radical>,
+    rng: &mut StdRng) -> (AnnealState<Radical>, Vec<f64>) {
+
+    anneal_with(empirical, opts.baseline, schedule, state, rng, caso, |newrads| calcola_fld(newrads, fld, opts))
+}
+
+// Same step for any kind of radical, like `mc_fit_with`
+pub fn anneal_with<R, F, S>(
+    empirical: &[f64],
+    baseline: Baseline,
+    schedule: &Annealing,
+    mut state: AnnealState<R>,
+    rng: &mut StdRng,
+    randomize: F,
+    simulate: S) -> (AnnealState<R>, Vec<f64>)
+where
+    R: Clone,
+    F: Fn(&[R], &mut StdRng) -> Vec<R>,
+    S: Fn(&[R]) -> Vec<f64>,
+{
+    let newrads = randomize(&state.rads, rng);
+    let (newsigma, newteor) = errore_with(empirical, simulate(&newrads), baseline);
+
+    if metropolis(state.sigma, newsigma, state.temperature, rng) {
+        state.sigma = newsigma;
+        state.rads = newrads;
+    }
+
+    if state.sigma < state.best_sigma {
+        state.best_sigma = state.sigma;
+        state.best = state.rads.clone();
+        state.stall = 0;
+    } else {
+        state.stall += 1;
+    }
+
+    state.temperature *= schedule.cooling;
+
+    // Stuck: start again from the best, hot
+    if schedule.reheat_after > 0 && state.stall >= schedule.reheat_after && state.reheated < schedule.reheats {
+        state.rads = state.best.clone();
+        state.sigma = state.best_sigma;
+        state.temperature = schedule.temperature;
+        state.stall = 0;
+        state.reheated += 1;
+    }
+
+    (state, newteor)
+}
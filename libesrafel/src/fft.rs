@@ -0,0 +1,76 @@
+// Radix-2 FFT and the linear convolution built on it
+use crate::complex::Complex;
+
+// In place, iterative Cooley-Tukey; the length must be a power of two.
+// `inverse` also divides by the length.
+pub(crate) fn fft(data: &mut [Complex], inverse: bool) {
+    let n = data.len();
+    debug_assert!(n.is_power_of_two());
+
+    // Bit reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+
+    // Twiddles of the last stage, computed one by one: less round-off than powers
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let twiddles: Vec<Complex> = (0..n / 2)
+        .map(|k| Complex::new(0.0, sign * 2.0 * std::f64::consts::PI * k as f64 / n as f64).exp())
+        .collect();
+
+    let mut len = 2;
+    while len <= n {
+        let stride = n / len;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let a = data[start + k];
+                let b = data[start + k + len / 2] * twiddles[k * stride];
+                data[start + k] = a + b;
+                data[start + k + len / 2] = a - b;
+            }
+        }
+        len <<= 1;
+    }
+
+    if inverse {
+        let k = 1.0 / n as f64;
+        for x in data.iter_mut() {
+            *x = x.scale(k);
+        }
+    }
+}
+
+// c[m] = Σ a[j]·b[m-j], zero padded so nothing wraps around
+pub(crate) fn convolve(a: &[f64], b: &[f64]) -> Vec<f64> {
+    if a.is_empty() || b.is_empty() {
+        return Vec::new();
+    }
+
+    let len = a.len() + b.len() - 1;
+    let n = len.next_power_of_two();
+    let pad = |v: &[f64]| {
+        let mut c: Vec<Complex> = v.iter().map(|x| Complex::new(*x, 0.0)).collect();
+        c.resize(n, Complex::default());
+        c
+    };
+
+    let mut fa = pad(a);
+    let mut fb = pad(b);
+    fft(&mut fa, false);
+    fft(&mut fb, false);
+    for (x, y) in fa.iter_mut().zip(fb.iter()) {
+        *x = *x * *y;
+    }
+    fft(&mut fa, true);
+
+    fa.into_iter().take(len).map(|c| c.re).collect()
+}
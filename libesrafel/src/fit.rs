@@ -0,0 +1,481 @@
+// Local refinement, after the Monte Carlo walk got close: Levenberg–Marquardt on the
+// parameters with nonzero var, with finite difference Jacobians of the whole simulation
+use crate::{Param, Radical};
+use crate::eprft::{calcola_smooth, check_pars, errore_with, SimOptions};
+use crate::linalg;
+use serde::{Serialize, Deserialize};
+
+// When to stop
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LevMar {
+    pub tolerance: f64,  // Relative decrease of the squared residuals below which we're done
+    pub max_iters: usize,
+}
+
+impl Default for LevMar {
+    fn default() -> Self {
+        LevMar { tolerance: 1e-8, max_iters: 100 }
+    }
+}
+
+// Result of a refinement: the same as `mc_fit`, plus how it went
+#[derive(Clone, Debug)]
+pub struct Refinement {
+    pub sigma: f64,
+    pub teor: Vec<f64>,  // Normalized like the spectrum of `mc_fit`
+    pub rads: Vec<Radical>,
+    pub iters: usize,
+    pub converged: bool,  // False if it ran out of iterations
+}
+
+// The parameters `caso` moves. Spin and eqs are discrete: derivatives make no sense
+// for them, but with `eqs` the number of equivalent nuclei is moved too, in whole steps.
+// Every parameter comes with its name, for reports.
+fn params_mut(rad: &mut Radical, eqs: bool) -> Vec<(String, &mut Param)> {
+    let mut pars = vec![
+        ("lwa".to_string(), &mut rad.lwa),
+        ("lwb".to_string(), &mut rad.lwb),
+        ("lwc".to_string(), &mut rad.lwc),
+        ("lrtz".to_string(), &mut rad.lrtz),
+        ("lwl".to_string(), &mut rad.lwl),
+        ("phase".to_string(), &mut rad.phase),
+        ("amount".to_string(), &mut rad.amount),
+        ("dh1".to_string(), &mut rad.dh1),
+        ("g".to_string(), &mut rad.g),
+    ];
+    for (i, nuc) in rad.nucs.iter_mut().enumerate() {
+        pars.push((format!("hpf {}", i + 1), &mut nuc.hpf));
+        if eqs {
+            pars.push((format!("eqs {}", i + 1), &mut nuc.eqs));
+        }
+    }
+    pars
+}
+
+// Names of the varied parameters, as "rad 1 lwa" or "rad 2 hpf 1"
+pub fn get_varied_names(rads: &[Radical], eqs: bool) -> Vec<String> {
+    let mut rads = rads.to_vec();
+    rads.iter_mut().enumerate()
+        .flat_map(|(i, rad)| {
+            params_mut(rad, eqs).into_iter()
+                .filter(|(_, par)| par.var != 0.0)
+                .map(move |(name, _)| format!("rad {} {}", i + 1, name))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+// Values of the parameters with nonzero var, radical after radical
+pub(crate) fn get_varied(rads: &[Radical], eqs: bool) -> Vec<f64> {
+    let mut rads = rads.to_vec();
+    rads.iter_mut()
+        .flat_map(|rad| params_mut(rad, eqs))
+        .filter(|(_, par)| par.var != 0.0)
+        .map(|(_, par)| par.val)
+        .collect()
+}
+
+// Steps of the varied parameters, in the same order
+pub(crate) fn get_steps(rads: &[Radical], eqs: bool) -> Vec<f64> {
+    let mut rads = rads.to_vec();
+    rads.iter_mut()
+        .flat_map(|rad| params_mut(rad, eqs))
+        .filter(|(_, par)| par.var != 0.0)
+        .map(|(_, par)| par.var.abs())
+        .collect()
+}
+
+// Copy of `rads` with new values of the varied parameters, within the bounds of `check_pars`;
+// at least one nucleus per group
+pub(crate) fn set_varied(rads: &[Radical], values: &[f64], eqs: bool) -> Vec<Radical> {
+    let mut rads = rads.to_vec();
+    let mut values = values.iter();
+    for rad in rads.iter_mut() {
+        for (_, par) in params_mut(rad, eqs).into_iter().filter(|(_, par)| par.var != 0.0) {
+            if let Some(val) = values.next() {
+                par.val = *val;
+            }
+        }
+        if eqs {
+            for nuc in rad.nucs.iter_mut() {
+                nuc.eqs.val = nuc.eqs.val.round().max(1.0);
+            }
+        }
+    }
+    rads.into_iter().map(check_pars).collect()
+}
+
+// exp - normalized teor, on the points `errore` compares.
+// Lines aren't snapped to the grid, or small steps wouldn't change anything;
+// run `errore` on `calcola_fld` to compare the sigma with a Monte Carlo one.
+pub(crate) fn residuals(empirical: &[f64], fld: &[f64], opts: &SimOptions, rads: &[Radical]) -> (Vec<f64>, Vec<f64>) {
+    let (_, teor) = errore_with(empirical, calcola_smooth(rads, fld, opts), opts.baseline);
+    let res = (1..teor.len()).map(|j| empirical[j] - teor[j]).collect();
+    (res, teor)
+}
+
+// Central differences of the residuals, one column per varied parameter.
+// Steps are a small fraction of var; a parameter stuck at a bound gets the one sided difference.
+pub(crate) fn jacobian(empirical: &[f64], fld: &[f64], opts: &SimOptions, rads: &[Radical]) -> Vec<Vec<f64>> {
+    let x = get_varied(rads, false);
+    let steps = get_steps(rads, false);
+
+    (0..x.len()).map(|k| {
+        let h = 1e-3 * steps[k];
+        let mut plus = x.clone();
+        let mut minus = x.clone();
+        plus[k] += h;
+        minus[k] -= h;
+
+        let plus = set_varied(rads, &plus, false);
+        let minus = set_varied(rads, &minus, false);
+        let dx = get_varied(&plus, false)[k] - get_varied(&minus, false)[k];
+        if dx == 0.0 {
+            return vec![0.0; empirical.len().saturating_sub(1)];
+        }
+
+        let (r_plus, _) = residuals(empirical, fld, opts, &plus);
+        let (r_minus, _) = residuals(empirical, fld, opts, &minus);
+        r_plus.iter().zip(r_minus.iter()).map(|(p, m)| (p - m) / dx).collect()
+    }).collect()
+}
+
+// JᵀJ and Jᵀr from the columns of J
+pub(crate) fn normal_equations(jac: &[Vec<f64>], res: &[f64]) -> (Vec<Vec<f64>>, Vec<f64>) {
+    let n = jac.len();
+    let mut jtj = vec![vec![0.0; n]; n];
+    for i in 0..n {
+        for j in i..n {
+            jtj[i][j] = jac[i].iter().zip(jac[j].iter()).map(|(a, b)| a * b).sum();
+            jtj[j][i] = jtj[i][j];
+        }
+    }
+    let jtr = jac.iter().map(|col| col.iter().zip(res.iter()).map(|(a, b)| a * b).sum()).collect();
+    (jtj, jtr)
+}
+
+fn sum_of_squares(res: &[f64]) -> f64 {
+    res.iter().map(|r| r * r).sum()
+}
+
+// Same sigma as `errore`
+fn sigma_of(res: &[f64]) -> f64 {
+    (sum_of_squares(res) / res.len().max(1) as f64).sqrt()
+}
+
+// Levenberg–Marquardt with Marquardt's scaling: the damping follows the diagonal of JᵀJ,
+// so parameters of any magnitude move in step
+pub fn levenberg_marquardt(
+    empirical: &[f64],
+    fld: &[f64],
+    opts: &SimOptions,
+    rads: Vec<Radical>,
+    lm: &LevMar) -> Refinement {
+
+    let mut rads: Vec<Radical> = rads.into_iter().map(check_pars).collect();
+    let (mut res, mut teor) = residuals(empirical, fld, opts, &rads);
+    let mut cost = sum_of_squares(&res);
+    let mut lambda = 1e-3;
+
+    let mut x = get_varied(&rads, false);
+    if x.is_empty() {
+        return Refinement { sigma: sigma_of(&res), teor, rads, iters: 0, converged: true };
+    }
+
+    for iter in 1..=lm.max_iters {
+        let jac = jacobian(empirical, fld, opts, &rads);
+        let (mut jtj, mut jtr) = normal_equations(&jac, &res);
+        let max_diag = (0..x.len()).map(|k| jtj[k][k]).fold(0.0, f64::max);
+        if max_diag == 0.0 {
+            return Refinement { sigma: sigma_of(&res), teor, rads, iters: iter, converged: true };
+        }
+
+        // Parameters the spectrum doesn't depend on (the amount of a lone radical,
+        // after the normalization) would only drift on round-off: keep them still
+        for k in 0..x.len() {
+            if jtj[k][k] <= 1e-12 * max_diag {
+                for row in jtj.iter_mut() {
+                    row[k] = 0.0;
+                }
+                jtj[k] = vec![0.0; x.len()];
+                jtj[k][k] = max_diag;
+                jtr[k] = 0.0;
+            }
+        }
+
+        // Raise the damping until the step goes downhill
+        loop {
+            let mut a = jtj.clone();
+            for (k, row) in a.iter_mut().enumerate() {
+                row[k] += lambda * jtj[k][k];
+            }
+
+            // r = exp - model, so J is minus the derivative of the model
+            let step = linalg::solve(a, jtr.clone());
+            if let Some(step) = step {
+                let newx: Vec<f64> = x.iter().zip(step.iter()).map(|(x, d)| x - d).collect();
+                let newrads = set_varied(&rads, &newx, false);
+                let (newres, newteor) = residuals(empirical, fld, opts, &newrads);
+                let newcost = sum_of_squares(&newres);
+
+                if newcost < cost {
+                    let decrease = (cost - newcost) / cost;
+                    x = get_varied(&newrads, false);
+                    rads = newrads;
+                    res = newres;
+                    teor = newteor;
+                    cost = newcost;
+                    lambda = (lambda / 10.0).max(1e-12);
+
+                    if decrease < lm.tolerance {
+                        return Refinement { sigma: sigma_of(&res), teor, rads, iters: iter, converged: true };
+                    }
+                    break;
+                }
+            }
+
+            lambda *= 10.0;
+            // No step goes downhill anymore: this is the minimum
+            if lambda > 1e12 {
+                return Refinement { sigma: sigma_of(&res), teor, rads, iters: iter, converged: true };
+            }
+        }
+    }
+
+    Refinement { sigma: sigma_of(&res), teor, rads, iters: lm.max_iters, converged: false }
+}
+
+// UNCERTAINTIES
+
+// Correlations above this, in absolute value, are worth a warning
+pub const STRONG_CORRELATION: f64 = 0.9;
+
+// Linearized error analysis at the end of a fit
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Uncertainty {
+    pub names: Vec<String>,  // Varied parameters, see `get_varied_names`
+    pub values: Vec<f64>,
+    // Standard errors; infinite for a parameter the spectrum doesn't depend on
+    pub errors: Vec<f64>,
+    pub correlation: Vec<Vec<f64>>,  // NaN next to such a parameter
+    pub sigma: f64,
+}
+
+impl Uncertainty {
+    // Pairs (i, j, r) with |r| above `STRONG_CORRELATION`
+    pub fn strongly_correlated(&self) -> Vec<(usize, usize, f64)> {
+        let n = self.names.len();
+        (0..n).flat_map(|i| (i + 1..n).map(move |j| (i, j)))
+            .map(|(i, j)| (i, j, self.correlation[i][j]))
+            .filter(|(_, _, r)| r.abs() > STRONG_CORRELATION)
+            .collect()
+    }
+}
+
+// Covariance s²(JᵀJ)⁻¹ of the parameters with nonzero var, from the Jacobian at `rads`,
+// with s² the squared residuals over the degrees of freedom.
+// Meaningful at a minimum only: run it after `levenberg_marquardt` or a converged simplex,
+// both fit the spectrum of `calcola_smooth`, the one differentiated here.
+// Parameters the spectrum doesn't determine get an infinite error and NaN correlations.
+// None without varied parameters, or with fewer points than parameters.
+pub fn uncertainties(empirical: &[f64], fld: &[f64], opts: &SimOptions, rads: &[Radical]) -> Option<Uncertainty> {
+    let rads: Vec<Radical> = rads.iter().cloned().map(check_pars).collect();
+    let names = get_varied_names(&rads, false);
+    let values = get_varied(&rads, false);
+    let n = values.len();
+
+    let (res, _) = residuals(empirical, fld, opts, &rads);
+    if n == 0 || res.len() <= n {
+        return None;
+    }
+    let s2 = sum_of_squares(&res) / (res.len() - n) as f64;
+
+    let jac = jacobian(empirical, fld, opts, &rads);
+    let (jtj, _) = normal_equations(&jac, &res);
+
+    // Spectra are normalized: with every amount varied, only their ratios count.
+    // Keep the first one fixed, its error is not determinable.
+    let fixed = if rads.iter().all(|rad| rad.amount.var != 0.0) {
+        names.iter().position(|name| name == "rad 1 amount")
+    } else {
+        None
+    };
+
+    // Only the parameters the spectrum depends on can be inverted
+    let max_diag = (0..n).map(|k| jtj[k][k]).fold(0.0, f64::max);
+    let free: Vec<usize> = (0..n).filter(|k| Some(*k) != fixed && jtj[*k][*k] > 1e-12 * max_diag).collect();
+    let sub: Vec<Vec<f64>> = free.iter().map(|i| free.iter().map(|j| jtj[*i][*j]).collect()).collect();
+    let inv = linalg::inverse(sub)?;
+
+    let mut cov = vec![vec![f64::NAN; n]; n];
+    for (a, i) in free.iter().enumerate() {
+        for (b, j) in free.iter().enumerate() {
+            cov[*i][*j] = s2 * inv[a][b];
+        }
+    }
+
+    // Round-off can leave a variance at or below zero: not determinable either
+    let errors: Vec<f64> = (0..n).map(|k| if cov[k][k] > 0.0 { cov[k][k].sqrt() } else { f64::INFINITY }).collect();
+    let correlation = (0..n).map(|i| {
+        (0..n).map(|j| {
+            if i == j {
+                1.0
+            } else if errors[i].is_finite() && errors[j].is_finite() {
+                cov[i][j] / (errors[i] * errors[j])
+            } else {
+                f64::NAN
+            }
+        }).collect()
+    }).collect();
+
+    Some(Uncertainty { names, values, errors, correlation, sigma: sigma_of(&res) })
+}
+
+// NELDER–MEAD
+
+// Derivative free: only compares sigmas, so it doesn't mind eqs moving in whole steps
+// or lrtz stuck at its bounds. The first simplex spans one var along every varied parameter.
+#[derive(Clone, Debug)]
+pub struct Simplex {
+    rads: Vec<Radical>,  // Everything but the varied values
+    vertices: Vec<Vec<f64>>,
+    sigmas: Vec<f64>,
+    teors: Vec<Vec<f64>>,
+    steps: Vec<f64>,
+    // Converged when the sigmas spread less than this, relatively,
+    // or when the simplex is this small, in units of var
+    pub tolerance: f64,
+}
+
+impl Simplex {
+    pub fn new(empirical: &[f64], fld: &[f64], opts: &SimOptions, rads: Vec<Radical>) -> Self {
+        let x0 = get_varied(&rads, true);
+        let steps = get_steps(&rads, true);
+
+        let mut vertices = vec![x0.clone()];
+        for (k, step) in steps.iter().enumerate() {
+            let mut x = x0.clone();
+            x[k] += step;
+            vertices.push(x);
+        }
+
+        let mut simplex = Simplex {
+            rads,
+            vertices: Vec::new(),
+            sigmas: Vec::new(),
+            teors: Vec::new(),
+            steps,
+            tolerance: 1e-8,
+        };
+        for x in vertices {
+            let (sigma, teor) = simplex.evaluate(empirical, fld, opts, &x);
+            simplex.vertices.push(x);
+            simplex.sigmas.push(sigma);
+            simplex.teors.push(teor);
+        }
+        simplex
+    }
+
+    fn evaluate(&self, empirical: &[f64], fld: &[f64], opts: &SimOptions, x: &[f64]) -> (f64, Vec<f64>) {
+        let rads = set_varied(&self.rads, x, true);
+        errore_with(empirical, calcola_smooth(&rads, fld, opts), opts.baseline)
+    }
+
+    fn best(&self) -> usize {
+        (0..self.sigmas.len()).min_by(|i, j| self.sigmas[*i].total_cmp(&self.sigmas[*j])).unwrap_or(0)
+    }
+
+    pub fn converged(&self) -> bool {
+        let lo = self.sigmas.iter().cloned().fold(f64::INFINITY, f64::min);
+        let hi = self.sigmas.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        if self.vertices.len() < 2 || hi - lo <= self.tolerance * lo {
+            return true;
+        }
+
+        // An exact match leaves no spread to compare: look at the size then
+        let best = &self.vertices[self.best()];
+        self.vertices.iter().all(|x| {
+            x.iter().zip(best.iter()).zip(self.steps.iter()).all(|((x, b), step)| (x - b).abs() <= self.tolerance * step)
+        })
+    }
+
+    pub fn sigma(&self) -> f64 {
+        self.sigmas[self.best()]
+    }
+
+    // Best vertex, as radicals
+    pub fn rads(&self) -> Vec<Radical> {
+        set_varied(&self.rads, &self.vertices[self.best()], true)
+    }
+}
+
+// One Nelder–Mead iteration: reflection, expansion, contraction or shrink.
+// Returns the best vertex like `mc_fit` returns the best radicals.
+pub fn nelder_mead(
+    empirical: &[f64],
+    fld: &[f64],
+    opts: &SimOptions,
+    simplex: &mut Simplex) -> (f64, Vec<f64>, Vec<Radical>) {
+
+    if !simplex.converged() {
+        nelder_mead_step(empirical, fld, opts, simplex);
+    }
+
+    let best = simplex.best();
+    (simplex.sigmas[best], simplex.teors[best].clone(), simplex.rads())
+}
+
+fn nelder_mead_step(empirical: &[f64], fld: &[f64], opts: &SimOptions, simplex: &mut Simplex) {
+    // Worst last
+    let mut order: Vec<usize> = (0..simplex.vertices.len()).collect();
+    order.sort_by(|i, j| simplex.sigmas[*i].total_cmp(&simplex.sigmas[*j]));
+    let (best, worst, second) = (order[0], order[order.len() - 1], order[order.len() - 2]);
+
+    let n = simplex.vertices[0].len();
+    let centroid: Vec<f64> = (0..n).map(|k| {
+        order[..order.len() - 1].iter().map(|v| simplex.vertices[*v][k]).sum::<f64>() / n as f64
+    }).collect();
+    let along = |t: f64| -> Vec<f64> {
+        centroid.iter().zip(simplex.vertices[worst].iter()).map(|(c, w)| c + t * (c - w)).collect()
+    };
+
+    let reflected = along(1.0);
+    let (sigma_r, teor_r) = simplex.evaluate(empirical, fld, opts, &reflected);
+
+    let (x, sigma, teor) = if sigma_r < simplex.sigmas[best] {
+        let expanded = along(2.0);
+        let (sigma_e, teor_e) = simplex.evaluate(empirical, fld, opts, &expanded);
+        if sigma_e < sigma_r { (expanded, sigma_e, teor_e) } else { (reflected, sigma_r, teor_r) }
+    } else if sigma_r < simplex.sigmas[second] {
+        (reflected, sigma_r, teor_r)
+    } else {
+        // Contract toward the better of the reflected and the worst
+        let (contracted, limit) = if sigma_r < simplex.sigmas[worst] {
+            (along(0.5), sigma_r)
+        } else {
+            (along(-0.5), simplex.sigmas[worst])
+        };
+        let (sigma_c, teor_c) = simplex.evaluate(empirical, fld, opts, &contracted);
+        if sigma_c < limit {
+            (contracted, sigma_c, teor_c)
+        } else {
+            // Shrink everything toward the best
+            let top = simplex.vertices[best].clone();
+            for v in 0..simplex.vertices.len() {
+                if v == best {
+                    continue;
+                }
+                let x: Vec<f64> = top.iter().zip(simplex.vertices[v].iter()).map(|(b, x)| b + 0.5 * (x - b)).collect();
+                let (sigma, teor) = simplex.evaluate(empirical, fld, opts, &x);
+                simplex.vertices[v] = x;
+                simplex.sigmas[v] = sigma;
+                simplex.teors[v] = teor;
+            }
+            return;
+        }
+    };
+
+    simplex.vertices[worst] = x;
+    simplex.sigmas[worst] = sigma;
+    simplex.teors[worst] = teor;
+}
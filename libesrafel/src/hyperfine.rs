@@ -0,0 +1,138 @@
+// Stick spectra: hyperfine line positions computed directly in gauss instead
+// of on the grid used by `eprft::calcola`, with exact multinomial intensities.
+// Every group of equivalent nuclei is split in its total spin F manifolds,
+// so second order and Breit-Rabi are exact per group.
+use crate::Radical;
+use serde::{Serialize, Deserialize};
+
+// How hyperfine lines are placed around the center of a radical
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LinePositions {
+    #[default]
+    FirstOrder,  // B0 - a·M, like ESR Commander
+    SecondOrder,  // Adds -a²/2B0 [F(F+1) - M²]
+    BreitRabi,  // Exact for a single group; shifts of different groups add up
+}
+
+// A single line of the stick spectrum
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Line {
+    pub field: f64,  // [G]
+    pub intensity: f64,  // Lines of a radical sum up to Π(2I+1)^n
+    pub m_i: Vec<f64>,  // Total M_I of every group of equivalent nuclei, as in `Radical::nucs`
+}
+
+// Multinomial counts of the total M of `eqs` nuclei with spin I, from -nI to nI
+pub(crate) fn multiplet(two_i: usize, eqs: usize) -> Vec<f64> {
+    let mut counts = vec![1.0];
+    for _ in 0..eqs {
+        let mut next = vec![0.0; counts.len() + two_i];
+        for (k, c) in counts.iter().enumerate() {
+            for slot in next.iter_mut().skip(k).take(two_i + 1) {
+                *slot += c;
+            }
+        }
+        counts = next;
+    }
+    counts
+}
+
+// Exact resonance field of the M line of a spin F coupled with `a` [G],
+// solving the Breit-Rabi transition energy for B with Newton iterations
+fn breit_rabi(a: f64, f: f64, m: f64, b0: f64) -> f64 {
+    if a == 0.0 || f == 0.0 {
+        return b0;
+    }
+
+    // Same line of the mirrored multiplet
+    let (a, m) = if a < 0.0 { (-a, -m) } else { (a, m) };
+    let dw = a * (f + 0.5);  // Zero field splitting
+    let k = 2.0 / (2.0 * f + 1.0);
+
+    let mut b = b0 - a * m;
+    for _ in 0..50 {
+        let x = b / dw;
+        let up = (1.0 + 2.0 * k * (m + 0.5) * x + x * x).max(1e-300).sqrt();
+        let down = (1.0 + 2.0 * k * (m - 0.5) * x + x * x).max(1e-300).sqrt();
+        let residual = dw / 2.0 * (up + down) - b0;
+        let slope = (k * (m + 0.5) + x) / (2.0 * up) + (k * (m - 0.5) + x) / (2.0 * down);
+
+        let step = residual / slope;
+        b -= step;
+        if step.abs() < 1e-9 {
+            break;
+        }
+    }
+    b
+}
+
+// Lines of a single group of equivalent nuclei, as (shift from B0, intensity, M)
+fn group_lines(spin: f64, a: f64, eqs: usize, b0: f64, positions: LinePositions) -> Vec<(f64, f64, f64)> {
+    let two_i = (2.0 * spin).round() as usize;
+    let counts = multiplet(two_i, eqs);
+    let half = (counts.len() - 1) as f64 / 2.0;  // nI
+
+    if positions == LinePositions::FirstOrder {
+        return counts.iter().enumerate()
+            .map(|(k, c)| (-a * (k as f64 - half), *c, k as f64 - half))
+            .collect();
+    }
+
+    // F manifolds: c(M = F) - c(M = F + 1) of them, each with lines from -F to F
+    let mut lines = Vec::new();
+    let mut top = counts.len() - 1;
+    while 2 * top >= counts.len() - 1 {
+        let f = top as f64 - half;
+        let above = counts.get(top + 1).copied().unwrap_or(0.0);
+        let manifolds = counts[top] - above;
+
+        if manifolds > 0.0 {
+            let mut m = -f;
+            while m <= f + 1e-9 {
+                let shift = match positions {
+                    LinePositions::SecondOrder => -a * m - a * a / (2.0 * b0) * (f * (f + 1.0) - m * m),
+                    _ => breit_rabi(a, f, m, b0) - b0,
+                };
+                lines.push((shift, manifolds, m));
+                m += 1.0;
+            }
+        }
+
+        if top == 0 {
+            break;
+        }
+        top -= 1;
+    }
+    lines
+}
+
+// First order stick spectrum, as offsets from the center of the sweep (dh1 included)
+pub fn stick_spectrum(rad: &Radical) -> Vec<Line> {
+    stick_spectrum_at(rad, rad.dh1.val, LinePositions::FirstOrder)
+}
+
+// Stick spectrum of a radical centered at `b0` [G]; dh1 is up to the caller.
+// Nuclei with no spin or no equivalents add no lines, and an M_I of 0,
+// so `m_i` keeps one entry per group.
+pub fn stick_spectrum_at(rad: &Radical, b0: f64, positions: LinePositions) -> Vec<Line> {
+    let mut sticks = vec![Line { field: b0, intensity: 1.0, m_i: Vec::new() }];
+
+    for nuc in &rad.nucs {
+        let eqs = nuc.eqs.val.round().max(0.0) as usize;
+        if eqs == 0 || nuc.spin.val <= 0.0 {
+            sticks.iter_mut().for_each(|s| s.m_i.push(0.0));
+            continue;
+        }
+
+        let lines = group_lines(nuc.spin.val, nuc.hpf.val, eqs, b0, positions);
+        sticks = sticks.iter().flat_map(|s| {
+            lines.iter().map(move |(shift, int, m)| {
+                let mut m_i = s.m_i.clone();
+                m_i.push(*m);
+                Line { field: s.field + shift, intensity: s.intensity * int, m_i }
+            })
+        }).collect();
+    }
+
+    sticks
+}
@@ -0,0 +1,93 @@
+// Bruker BES3T format, as written by Xepr.
+// A measure is split in two files: a text descriptor (.DSC) and the raw data (.DTA).
+// Only 1D field sweeps on a linear axis are supported.
+use super::{Acquisition, EsrafelIoError, Spectrum};
+use super::{descriptor, number, quantity, required, text, to_gauss};
+
+fn decode(dta: &[u8], irfmt: &str, big_endian: bool) -> Result<Vec<f64>, EsrafelIoError> {
+    let size = match irfmt {
+        "D" => 8,
+        "F" | "I" => 4,
+        "S" => 2,
+        "C" => 1,
+        other => return Err(EsrafelIoError::Unsupported { what: format!("IRFMT {}", other) }),
+    };
+
+    let values = dta.chunks_exact(size).map(|c| match (irfmt, big_endian) {
+        ("D", true) => f64::from_be_bytes(c.try_into().unwrap()),
+        ("D", false) => f64::from_le_bytes(c.try_into().unwrap()),
+        ("F", true) => f32::from_be_bytes(c.try_into().unwrap()) as f64,
+        ("F", false) => f32::from_le_bytes(c.try_into().unwrap()) as f64,
+        ("I", true) => i32::from_be_bytes(c.try_into().unwrap()) as f64,
+        ("I", false) => i32::from_le_bytes(c.try_into().unwrap()) as f64,
+        ("S", true) => i16::from_be_bytes(c.try_into().unwrap()) as f64,
+        ("S", false) => i16::from_le_bytes(c.try_into().unwrap()) as f64,
+        _ => c[0] as i8 as f64,
+    }).collect();
+
+    Ok(values)
+}
+
+impl Spectrum {
+    pub fn from_bes3t(dsc: &str, dta: &[u8]) -> Result<Self, EsrafelIoError> {
+        let map = descriptor(dsc);
+
+        if let Some(ytyp) = text(&map, "YTYP") {
+            if ytyp != "NODATA" {
+                return Err(EsrafelIoError::Unsupported { what: "2D dataset".into() });
+            }
+        }
+
+        if let Some(xtyp) = text(&map, "XTYP") {
+            if xtyp != "IDX" {
+                return Err(EsrafelIoError::Unsupported { what: format!("XTYP {} (non-linear axis)", xtyp) });
+            }
+        }
+
+        let points = number(&map, "XPTS")? as usize;
+        let xmin = number(&map, "XMIN")?;
+        let xwid = number(&map, "XWID")?;
+        let irfmt = required(&map, "IRFMT")?;
+
+        // Xepr default is big endian
+        let big_endian = text(&map, "BSEQ").map(|b| b != "LIT").unwrap_or(true);
+        let complex = text(&map, "IKKF").map(|k| k == "CPLX").unwrap_or(false);
+
+        let mut int = decode(dta, irfmt, big_endian)?;
+        if complex {
+            // Interleaved real and imaginary parts; keep the real one
+            int = int.into_iter().step_by(2).collect();
+        }
+
+        if int.len() != points {
+            let per_point = dta.len() / int.len().max(1);
+            return Err(EsrafelIoError::BinarySize {
+                expected: points * per_point,
+                found: dta.len(),
+            });
+        }
+
+        let unit = text(&map, "XUNI").unwrap_or("G");
+        let step = if points > 1 { xwid / (points - 1) as f64 } else { 0.0 };
+        let fld = (0..points).map(|i| to_gauss(xmin + step * i as f64, unit)).collect();
+
+        let meta = Acquisition {
+            title: text(&map, "TITL").map(String::from),
+            mwfq: number(&map, "MWFQ").ok().map(|hz| hz / 1e9),
+            power: number(&map, "MWPW").ok().map(|w| w * 1e3),
+            mod_amp: text(&map, "ModAmp").and_then(quantity).map(|(v, u)| to_gauss(v, u)),
+            mod_freq: text(&map, "ModFreq").and_then(quantity).map(|(v, u)| {
+                if u == "Hz" { v / 1e3 } else { v }
+            }),
+            gain: number(&map, "RCAG").ok(),
+            temperature: text(&map, "Temperature").and_then(quantity).map(|(v, _)| v),
+        };
+
+        Ok(Spectrum {
+            idx: (1..=points).collect(),
+            fld,
+            int,
+            meta,
+        })
+    }
+}
@@ -0,0 +1,375 @@
+// JCAMP-DX, the exchange format most of our collaborators can read and write.
+// Reading handles both `##XYDATA=(X++(Y..Y))`, in plain (AFFN) or compressed
+// form (SQZ, DIF, DUP), and `##XYPOINTS=(XY..XY)`.
+// Writing uses DIFDUP for uniform field axes, XYPOINTS otherwise.
+use super::{Acquisition, EsrafelIoError, Spectrum};
+
+// Labels are compared without spaces, dashes, slashes and underscores,
+// as the standard asks: "DATA TYPE" and "DATATYPE" are the same label.
+fn label(raw: &str) -> String {
+    raw.chars()
+        .filter(|c| !matches!(c, ' ' | '-' | '/' | '_'))
+        .collect::<String>()
+        .to_uppercase()
+}
+
+// Strip the inline comment ($$ ...) from a value
+fn value(raw: &str) -> &str {
+    match raw.find("$$") {
+        Some(i) => raw[..i].trim(),
+        None => raw.trim(),
+    }
+}
+
+// Y values of a single XYDATA line (the leading X is skipped).
+// Returns the values and whether the line ended in DIF form,
+// meaning the first value of the next line is a Y-check.
+fn asdf_line(line: &str, n: usize) -> Result<(Vec<f64>, bool), EsrafelIoError> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum Kind { Abs, Dif }
+
+    let mut tokens: Vec<(Kind, String)> = Vec::new();
+    let mut ys = Vec::new();
+    let mut last_kind = Kind::Abs;
+    let mut last_value = 0.0;
+
+    let parse = |token: &str, column: usize| -> Result<f64, EsrafelIoError> {
+        token.parse().map_err(|_| EsrafelIoError::Parse {
+            line: n,
+            column,
+            token: token.to_string(),
+            expected: "a JCAMP-DX value",
+        })
+    };
+
+    // Tokenize: every SQZ, DIF or DUP character, sign or blank starts a new token.
+    // Plain (AFFN) numbers may carry a signed exponent, whose E would look like SQZ:
+    // "1.5E+03" is an exponent, "3300E12" is 3300 followed by 512.
+    let chars: Vec<char> = line.chars().collect();
+    let mut affn = false;
+    for (i, &c) in chars.iter().enumerate() {
+        let signed_next = matches!(chars.get(i + 1), Some('+') | Some('-'));
+        match c {
+            'E' | 'e' if affn && signed_next => {
+                if let Some((_, t)) = tokens.last_mut() { t.push('E'); }
+                continue;
+            }
+            '+' | '-' if tokens.last().map(|(_, t)| t.ends_with('E')).unwrap_or(false) => {
+                if let Some((_, t)) = tokens.last_mut() { t.push(c); }
+                continue;
+            }
+            '@' | 'A'..='I' | 'a'..='i' => {
+                let digit = match c {
+                    '@' => 0,
+                    'A'..='I' => c as i32 - 'A' as i32 + 1,
+                    _ => -(c as i32 - 'a' as i32 + 1),
+                };
+                tokens.push((Kind::Abs, digit.to_string()));
+                affn = false;
+            }
+            '%' | 'J'..='R' | 'j'..='r' => {
+                let digit = match c {
+                    '%' => 0,
+                    'J'..='R' => c as i32 - 'J' as i32 + 1,
+                    _ => -(c as i32 - 'j' as i32 + 1),
+                };
+                tokens.push((Kind::Dif, digit.to_string()));
+                affn = false;
+            }
+            'S'..='Z' | 's' => {
+                let count = if c == 's' { 9 } else { c as i32 - 'S' as i32 + 1 };
+                tokens.push((Kind::Abs, format!("*{}", count)));
+                affn = false;
+            }
+            '+' | '-' => {
+                tokens.push((Kind::Abs, c.to_string()));
+                affn = true;
+            }
+            ' ' | '\t' | ',' | ';' => {
+                tokens.push((Kind::Abs, String::new()));
+                affn = true;
+            }
+            _ => match tokens.last_mut() {
+                Some((_, t)) => t.push(c),
+                None => {
+                    tokens.push((Kind::Abs, c.to_string()));
+                    affn = true;
+                }
+            },
+        }
+    }
+
+    let mut first = true;  // The first number is the abscissa
+    for (column, (kind, token)) in tokens.into_iter().enumerate() {
+        if token.is_empty() || token == "+" || token == "-" {
+            continue;
+        }
+
+        if first {
+            first = false;
+            continue;
+        }
+
+        if let Some(count) = token.strip_prefix('*') {
+            // DUP: repeat the previous token, count includes the original
+            let count: usize = parse(count, column + 1)? as usize;
+            for _ in 1..count {
+                if last_kind == Kind::Dif {
+                    let y = ys.last().copied().unwrap_or(0.0) + last_value;
+                    ys.push(y);
+                } else {
+                    ys.push(last_value);
+                }
+            }
+            continue;
+        }
+
+        let v = parse(&token, column + 1)?;
+        match kind {
+            Kind::Abs => ys.push(v),
+            Kind::Dif => {
+                let y = ys.last().copied().unwrap_or(0.0) + v;
+                ys.push(y);
+            }
+        }
+        last_kind = kind;
+        last_value = v;
+    }
+
+    Ok((ys, last_kind == Kind::Dif))
+}
+
+// SQZ and DIF forms of an integer, e.g. 123 -> "A23", -7 -> "p"
+fn asdf(value: i64, dif: bool) -> String {
+    let digits = value.unsigned_abs().to_string();
+    let head = digits.as_bytes()[0] - b'0';
+    let lead = match (dif, value < 0) {
+        (false, _) if head == 0 => '@',
+        (false, false) => (b'A' + head - 1) as char,
+        (false, true) => (b'a' + head - 1) as char,
+        (true, _) if head == 0 => '%',
+        (true, false) => (b'J' + head - 1) as char,
+        (true, true) => (b'j' + head - 1) as char,
+    };
+    format!("{}{}", lead, &digits[1..])
+}
+
+// DUP form of a repeat count (including the original token)
+fn dup(count: usize) -> String {
+    let digits = count.to_string();
+    let head = digits.as_bytes()[0] - b'0';
+    let lead = if head == 9 { 's' } else { (b'S' + head - 1) as char };
+    format!("{}{}", lead, &digits[1..])
+}
+
+impl Spectrum {
+    pub fn from_jcampdx(content: &str) -> Result<Self, EsrafelIoError> {
+        let mut firstx = None;
+        let mut lastx = None;
+        let mut npoints = None;
+        let (mut xfactor, mut yfactor) = (1.0, 1.0);
+        let mut xunits = String::from("GAUSS");
+        let mut meta = Acquisition::default();
+
+        let mut xs: Vec<f64> = Vec::new();
+        let mut ys: Vec<f64> = Vec::new();
+
+        enum Table { None, XyData, XyPoints }
+        let mut table = Table::None;
+        let mut y_check = false;
+
+        for (n, line) in content.lines().enumerate() {
+            let n = n + 1;
+            let trimmed = line.trim();
+
+            if let Some(record) = trimmed.strip_prefix("##") {
+                let (key, raw) = record.split_once('=').unwrap_or((record, ""));
+                let key = label(key);
+                let val = value(raw);
+                let num = || -> Result<f64, EsrafelIoError> {
+                    val.parse().map_err(|_| EsrafelIoError::Parse {
+                        line: n,
+                        column: 1,
+                        token: val.to_string(),
+                        expected: "a number",
+                    })
+                };
+
+                table = Table::None;
+                match key.as_str() {
+                    "TITLE" => meta.title = Some(val.to_string()).filter(|t| !t.is_empty()),
+                    "FIRSTX" => firstx = Some(num()?),
+                    "LASTX" => lastx = Some(num()?),
+                    "NPOINTS" => npoints = Some(num()? as usize),
+                    "XFACTOR" => xfactor = num()?,
+                    "YFACTOR" => yfactor = num()?,
+                    "XUNITS" => xunits = val.to_uppercase(),
+                    // Bruker style EPR labels; MWFQ may come in Hz or GHz
+                    "$MWFQ" => meta.mwfq = Some(num()?).map(|f| if f > 1e6 { f / 1e9 } else { f }),
+                    ".OBSERVEFREQUENCY" => meta.mwfq = Some(num()? / 1e3),
+                    "$MWPW" => meta.power = Some(num()?),
+                    "$MODAMP" | "$RMA" => meta.mod_amp = Some(num()?),
+                    "$MODFREQ" | "$RMF" => meta.mod_freq = Some(num()?),
+                    "$TEMPERATURE" => meta.temperature = Some(num()?),
+                    "XYDATA" => {
+                        if !val.replace(' ', "").eq_ignore_ascii_case("(X++(Y..Y))") {
+                            return Err(EsrafelIoError::Unsupported { what: format!("XYDATA {}", val) });
+                        }
+                        table = Table::XyData;
+                        y_check = false;
+                    }
+                    "XYPOINTS" => table = Table::XyPoints,
+                    _ => {}
+                }
+                continue;
+            }
+
+            if trimmed.is_empty() || trimmed.starts_with("$$") {
+                continue;
+            }
+
+            match table {
+                Table::XyData => {
+                    let (mut line_ys, ends_in_dif) = asdf_line(value(trimmed), n)?;
+                    if y_check && !line_ys.is_empty() {
+                        line_ys.remove(0);
+                    }
+                    y_check = ends_in_dif;
+                    ys.extend(line_ys.into_iter().map(|y| y * yfactor));
+                }
+                Table::XyPoints => {
+                    let numbers: Vec<&str> = value(trimmed)
+                        .split(|c: char| c == ',' || c == ';' || c.is_whitespace())
+                        .filter(|t| !t.is_empty())
+                        .collect();
+                    for (i, pair) in numbers.chunks(2).enumerate() {
+                        match pair {
+                            [x, y] => {
+                                xs.push(super::parse_token::<f64>(x, n, 2 * i + 1, "a field value")? * xfactor);
+                                ys.push(super::parse_token::<f64>(y, n, 2 * i + 2, "an intensity value")? * yfactor);
+                            }
+                            // A field value without its intensity
+                            _ => return Err(EsrafelIoError::Parse {
+                                line: n,
+                                column: 2 * i + 1,
+                                token: pair[0].to_string(),
+                                expected: "a field value followed by its intensity",
+                            }),
+                        }
+                    }
+                }
+                Table::None => {}
+            }
+        }
+
+        if ys.is_empty() {
+            return Err(EsrafelIoError::MissingKey { key: "##XYDATA".into() });
+        }
+
+        // X++ tables are equally spaced between FIRSTX and LASTX
+        if xs.is_empty() {
+            let first = firstx.ok_or(EsrafelIoError::MissingKey { key: "##FIRSTX".into() })?;
+            let last = lastx.ok_or(EsrafelIoError::MissingKey { key: "##LASTX".into() })?;
+            let points = npoints.unwrap_or(ys.len());
+            if points != ys.len() {
+                return Err(EsrafelIoError::Unsupported {
+                    what: format!("XYDATA table: {} values for {} points", ys.len(), points),
+                });
+            }
+            let step = if points > 1 { (last - first) / (points - 1) as f64 } else { 0.0 };
+            xs = (0..points).map(|i| first + step * i as f64).collect();
+        }
+
+        let to_gauss = match xunits.as_str() {
+            u if u.starts_with("MT") || u.starts_with("MILLITESLA") => 10.0,
+            "T" | "TESLA" => 1e4,
+            _ => 1.0,
+        };
+
+        Ok(Spectrum {
+            idx: (1..=ys.len()).collect(),
+            fld: xs.into_iter().map(|x| x * to_gauss).collect(),
+            int: ys,
+            meta,
+        })
+    }
+
+    pub fn into_jcampdx(&self) -> String {
+        let mut out = String::new();
+        let n = self.int.len();
+        let first = self.fld.first().copied().unwrap_or(0.0);
+        let last = self.fld.last().copied().unwrap_or(0.0);
+        let step = if n > 1 { (last - first) / (n - 1) as f64 } else { 0.0 };
+
+        // X++ needs an equally spaced axis
+        let uniform = self.fld.iter().enumerate()
+            .all(|(i, x)| (x - (first + step * i as f64)).abs() <= 1e-6 * step.abs().max(1e-12));
+
+        // Integers with about nine significant digits
+        let ymax = self.int.iter().fold(0.0_f64, |m, y| m.max(y.abs()));
+        let yfactor = if ymax > 0.0 { ymax / 1e9 } else { 1.0 };
+
+        out.push_str(&format!("##TITLE={}\n", self.meta.title.as_deref().unwrap_or("Esrafel spectrum")));
+        out.push_str("##JCAMP-DX=5.01\n");
+        out.push_str("##DATA TYPE=EPR SPECTRUM\n");
+        out.push_str("##ORIGIN=Esrafel\n");
+        out.push_str("##OWNER=\n");
+        out.push_str("##XUNITS=GAUSS\n");
+        out.push_str("##YUNITS=ARBITRARY UNITS\n");
+        if let Some(f) = self.meta.mwfq { out.push_str(&format!("##$MWFQ={}\n", f * 1e9)); }
+        if let Some(p) = self.meta.power { out.push_str(&format!("##$MWPW={}\n", p)); }
+        if let Some(a) = self.meta.mod_amp { out.push_str(&format!("##$MODAMP={}\n", a)); }
+        if let Some(f) = self.meta.mod_freq { out.push_str(&format!("##$MODFREQ={}\n", f)); }
+        if let Some(t) = self.meta.temperature { out.push_str(&format!("##$TEMPERATURE={}\n", t)); }
+        out.push_str(&format!("##FIRSTX={}\n", first));
+        out.push_str(&format!("##LASTX={}\n", last));
+        out.push_str(&format!("##NPOINTS={}\n", n));
+        out.push_str("##XFACTOR=1\n");
+        out.push_str(&format!("##YFACTOR={:e}\n", yfactor));
+
+        if uniform && n > 0 {
+            let ints: Vec<i64> = self.int.iter().map(|y| (y / yfactor).round() as i64).collect();
+            out.push_str(&format!("##FIRSTY={}\n", ints[0] as f64 * yfactor));
+            out.push_str("##XYDATA=(X++(Y..Y))\n");
+
+            // Each line starts with the last value of the previous one (Y-check)
+            let mut start = 0;
+            loop {
+                let mut line = format!("{}{}", first + step * start as f64, asdf(ints[start], false));
+                let mut i = start + 1;
+                while i < n && line.len() < 70 {
+                    let d = ints[i] - ints[i - 1];
+                    let mut count = 1;
+                    while i + count < n && ints[i + count] - ints[i + count - 1] == d {
+                        count += 1;
+                    }
+                    line.push_str(&asdf(d, true));
+                    if count > 1 {
+                        line.push_str(&dup(count));
+                    }
+                    i += count;
+                }
+                out.push_str(&line);
+                out.push('\n');
+
+                if i >= n {
+                    // Closing Y-check for the last point, only after a DIF line:
+                    // a single point has none and would be read twice
+                    if n > 1 {
+                        out.push_str(&format!("{}{}\n", first + step * (n - 1) as f64, asdf(ints[n - 1], false)));
+                    }
+                    break;
+                }
+                start = i - 1;
+            }
+        } else {
+            out.push_str("##XYPOINTS=(XY..XY)\n");
+            for (x, y) in self.fld.iter().zip(self.int.iter()) {
+                out.push_str(&format!("{}, {}\n", x, y / yfactor));
+            }
+        }
+
+        out.push_str("##END=\n");
+        out
+    }
+}
@@ -0,0 +1,307 @@
+use crate::{Radical, Nucleus};
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+mod bes3t;
+mod jcampdx;
+mod text;
+mod winepr;
+
+pub use text::{ImportReport, SkipReason, SkippedRow, TextOptions};
+
+// Everything that can go wrong while reading a lab file.
+// Line and column numbers are 1-based, so they match what you see in an editor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EsrafelIoError {
+    // A token was found, but it cannot be read as the expected value
+    Parse { line: usize, column: usize, token: String, expected: &'static str },
+    // The file ends before a required value
+    UnexpectedEof { line: usize, expected: &'static str },
+    // A descriptor (e.g. Bruker .DSC) lacks a mandatory keyword
+    MissingKey { key: String },
+    // Binary data doesn't match the size announced by its descriptor
+    BinarySize { expected: usize, found: usize },
+    // Valid file, but it stores something we can't handle (2D data, unknown formats...)
+    Unsupported { what: String },
+}
+
+impl fmt::Display for EsrafelIoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EsrafelIoError::Parse { line, column, token, expected } => write!(
+                f, "line {}, column {}: cannot read {:?} as {}", line, column, token, expected
+            ),
+            EsrafelIoError::UnexpectedEof { line, expected } => write!(
+                f, "line {}: file ends before {}", line, expected
+            ),
+            EsrafelIoError::MissingKey { key } => write!(
+                f, "missing mandatory keyword {}", key
+            ),
+            EsrafelIoError::BinarySize { expected, found } => write!(
+                f, "expected {} bytes of data, found {}", expected, found
+            ),
+            EsrafelIoError::Unsupported { what } => write!(
+                f, "unsupported {}", what
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EsrafelIoError {}
+
+fn parse_token<T: FromStr>(
+    token: &str,
+    line: usize,
+    column: usize,
+    expected: &'static str) -> Result<T, EsrafelIoError> {
+
+    token.parse().map_err(|_| EsrafelIoError::Parse {
+        line,
+        column,
+        token: token.to_string(),
+        expected,
+    })
+}
+
+// Text of a lab file as read from disk.
+// Bruker descriptors are often Latin-1 (µ, °C in comments), so anything
+// that isn't valid UTF-8 is read one byte per char.
+pub fn decode_text(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(s) => s.to_string(),
+        Err(_) => bytes.iter().map(|&b| b as char).collect(),
+    }
+}
+
+// Line-oriented reader for files that store one value per line (SIM and friends)
+struct ValueLines<'a> {
+    lines: std::str::Lines<'a>,
+    line: usize,
+}
+
+impl<'a> ValueLines<'a> {
+    fn new(data: &'a str) -> Self {
+        ValueLines { lines: data.lines(), line: 0 }
+    }
+
+    fn next<T: FromStr>(&mut self, expected: &'static str) -> Result<T, EsrafelIoError> {
+        self.line += 1;
+        match self.lines.next() {
+            Some(l) => parse_token(l.trim(), self.line, 1, expected),
+            None => Err(EsrafelIoError::UnexpectedEof { line: self.line, expected }),
+        }
+    }
+}
+
+type Descriptor = HashMap<String, (usize, String)>;
+
+// Split a keyword-value descriptor (Bruker .DSC and .par) in a keyword -> (line, value) map.
+// Comments (*), section headers (#) and device blocks (.DVC) are skipped;
+// keywords repeated in later layers override the previous ones.
+fn descriptor(dsc: &str) -> Descriptor {
+    let mut map = HashMap::new();
+    let mut lines = dsc.lines().enumerate();
+
+    while let Some((n, line)) = lines.next() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('*') || line.starts_with('#') || line.starts_with('.') {
+            continue;
+        }
+
+        let (key, mut value) = match line.split_once(char::is_whitespace) {
+            Some((k, v)) => (k, v.trim().to_string()),
+            None => (line, String::new()),
+        };
+
+        // A trailing backslash continues the value on the next line
+        while value.ends_with('\\') {
+            value.pop();
+            match lines.next() {
+                Some((_, next)) => value.push_str(next.trim()),
+                None => break,
+            }
+        }
+
+        map.insert(key.to_string(), (n + 1, value.trim_matches('\'').to_string()));
+    }
+
+    map
+}
+
+fn text<'a>(map: &'a Descriptor, key: &str) -> Option<&'a str> {
+    map.get(key).map(|(_, v)| v.as_str())
+}
+
+fn required<'a>(map: &'a Descriptor, key: &str) -> Result<&'a str, EsrafelIoError> {
+    text(map, key).ok_or_else(|| EsrafelIoError::MissingKey { key: key.to_string() })
+}
+
+fn number(map: &Descriptor, key: &str) -> Result<f64, EsrafelIoError> {
+    let (line, value) = map.get(key).ok_or_else(|| EsrafelIoError::MissingKey { key: key.to_string() })?;
+    quantity(value).map(|(v, _)| v).ok_or_else(|| EsrafelIoError::Parse {
+        line: *line,
+        column: 2,
+        token: value.to_string(),
+        expected: "a number",
+    })
+}
+
+// "1.000 G" -> (1.0, "G"); unit may be missing
+fn quantity(value: &str) -> Option<(f64, &str)> {
+    let mut tokens = value.split_whitespace();
+    let num = tokens.next()?.parse().ok()?;
+    Some((num, tokens.next().unwrap_or("").trim_matches('\'')))
+}
+
+// Gauss is our field unit everywhere
+fn to_gauss(value: f64, unit: &str) -> f64 {
+    match unit {
+        "mT" => value * 10.0,
+        "T" => value * 10000.0,
+        _ => value,
+    }
+}
+
+// Acquisition parameters, when the file format stores them.
+// Fields are in gauss, GHz, kHz and mW, whatever the source unit.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Acquisition {
+    pub title: Option<String>,
+    pub mwfq: Option<f64>,  // Microwave frequency [GHz]
+    pub power: Option<f64>,  // Microwave power [mW]
+    pub mod_amp: Option<f64>,  // Modulation amplitude [G]
+    pub mod_freq: Option<f64>,  // Modulation frequency [kHz]
+    pub gain: Option<f64>,  // Receiver gain [dB]
+    pub temperature: Option<f64>,  // Sample temperature [K]
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Spectrum {
+    idx: Vec<usize>,
+    fld: Vec<f64>,
+    int: Vec<f64>,
+    #[serde(default)]
+    meta: Acquisition,
+}
+
+impl Spectrum {
+    // Spectrum from a field axis and its intensities, e.g. to export a simulation
+    pub fn new(fld: Vec<f64>, int: Vec<f64>) -> Self {
+        Spectrum {
+            idx: (1..=int.len()).collect(),
+            fld,
+            int,
+            meta: Acquisition::default(),
+        }
+    }
+
+    pub fn from_ascii(content: &str) -> Result<Self, EsrafelIoError> {
+        let mut imp = Spectrum::default();
+
+        for (n, line) in content.lines().enumerate() {
+            // split_whitespace already trims the line, like Python's strip
+            let cols: Vec<&str> = line.split_whitespace().collect();
+
+            // Only index, field and intensity rows are data;
+            // headers and blank lines are skipped.
+            if cols.len() == 3 {
+                imp.idx.push(parse_token(cols[0], n + 1, 1, "an index")?);
+                imp.fld.push(parse_token(cols[1], n + 1, 2, "a field value")?);
+                imp.int.push(parse_token(cols[2], n + 1, 3, "an intensity value")?);
+            }
+        };
+
+        Ok(imp)
+    }
+
+    pub fn into_tuple(&self) -> (Vec<usize>, Vec<f64>, Vec<f64>) {
+        (self.idx.clone(), self.fld.clone(), self.int.clone())
+    }
+
+    pub fn get_int(&self) -> Vec<f64> {
+        self.int.clone()
+    }
+
+    pub fn get_fld(&self) -> Vec<f64> {
+        self.fld.clone()
+    }
+
+    pub fn get_meta(&self) -> &Acquisition {
+        &self.meta
+    }
+
+    // Acquisition parameters to export along with the data
+    pub fn set_meta(&mut self, meta: Acquisition) {
+        self.meta = meta;
+    }
+
+    // Serialize as JSON
+    pub fn into_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(&self)
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct SimulationState {
+    points: i32,
+    sweep: i32,
+    rads: Vec<Radical>,
+    #[serde(default)]
+    mwfq: Option<f64>,  // Microwave frequency [GHz]; SIM files don't store it
+}
+
+impl SimulationState {
+    pub fn from_simfile(data: &str) -> Result<Self, EsrafelIoError> {
+        let mut lines = ValueLines::new(data);
+        let mut rads = Vec::new();
+
+        let how_many_rads: i32 = lines.next("how many radicals")?;
+        let points: i32 = lines.next("the number of points")?;
+        let sweep: i32 = lines.next("the sweep width")?;
+
+        for _ in 0..how_many_rads {
+            let amount: f64 = lines.next("the radical amount")?;
+            let dh1: f64 = lines.next("the radical center")?;
+            let lwa: f64 = lines.next("the line width")?;
+            let lrtz: f64 = lines.next("the Lorentzian percentage")?;
+
+            let how_many_const: i32 = lines.next("how many hyperfine constants")?;
+
+            let mut nucs = Vec::new();
+            for _ in 0..how_many_const {
+                let eqs: i32 = lines.next("how many equivalent nuclei")?;
+                let spin: f64 = lines.next("the nuclear spin")?;
+                let hpf: f64 = lines.next("the hyperfine constant")?;
+
+                nucs.push(Nucleus::set(spin, hpf, eqs as f64));
+            }  // for nuc in nucs
+
+            rads.push(Radical::set(lwa, lrtz, amount, dh1, nucs));
+        }  // for rad in rads
+
+        Ok(SimulationState {
+            points,
+            sweep,
+            rads,
+            mwfq: None,
+        })
+    }
+
+    pub fn into_tuple(&self) -> (i32, i32, Vec<Radical>) {
+        (self.points, self.sweep, self.rads.clone())
+    }
+
+    pub fn get_rads(&self) -> Vec<Radical> {
+        self.rads.clone()
+    }
+
+    pub fn get_mwfq(&self) -> Option<f64> {
+        self.mwfq
+    }
+
+    pub fn into_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(&self)
+    }
+}
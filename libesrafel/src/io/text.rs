@@ -0,0 +1,162 @@
+// Delimited text spectra (CSV, TSV, whitespace columns) exported by every
+// instrument software under the sun. Unlike `from_ascii`, rows that don't
+// fit the layout are skipped and reported, not silently dropped.
+use super::{EsrafelIoError, Spectrum};
+use serde::{Serialize, Deserialize};
+use std::fmt;
+
+// How to read a delimited text file.
+// Column indexes are 0-based; without a field column, the row number is used as axis.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TextOptions {
+    pub delimiter: Option<char>,  // None splits on any whitespace
+    pub comment: Option<String>,  // Lines starting with this prefix are skipped
+    pub header_rows: usize,  // Rows to skip before data
+    pub field_col: Option<usize>,
+    pub intensity_col: usize,
+    pub decimal_comma: bool,  // 3262,75 instead of 3262.75
+}
+
+impl Default for TextOptions {
+    // Two columns, field and intensity
+    fn default() -> Self {
+        TextOptions {
+            delimiter: None,
+            comment: Some("#".into()),
+            header_rows: 0,
+            field_col: Some(0),
+            intensity_col: 1,
+            decimal_comma: false,
+        }
+    }
+}
+
+impl TextOptions {
+    // Index, field and intensity, like the files read by `Spectrum::from_ascii`
+    pub fn three_columns() -> Self {
+        TextOptions {
+            field_col: Some(1),
+            intensity_col: 2,
+            ..TextOptions::default()
+        }
+    }
+
+    pub fn csv() -> Self {
+        TextOptions {
+            delimiter: Some(','),
+            ..TextOptions::default()
+        }
+    }
+
+    pub fn tsv() -> Self {
+        TextOptions {
+            delimiter: Some('\t'),
+            ..TextOptions::default()
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SkipReason {
+    Header,
+    Comment,
+    Columns { found: usize, needed: usize },  // Row too short for the selected columns
+    NotNumeric { column: usize, token: String },
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SkipReason::Header => write!(f, "header"),
+            SkipReason::Comment => write!(f, "comment"),
+            SkipReason::Columns { found, needed } => write!(f, "{} columns, {} needed", found, needed),
+            SkipReason::NotNumeric { column, token } => write!(f, "column {}: {:?} is not a number", column, token),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SkippedRow {
+    pub line: usize,  // 1-based
+    pub reason: SkipReason,
+}
+
+// What happened to every non-blank row of the file
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ImportReport {
+    pub rows: usize,  // Rows read as data
+    pub skipped: Vec<SkippedRow>,
+}
+
+impl Spectrum {
+    pub fn from_text(content: &str, opts: &TextOptions) -> Result<(Self, ImportReport), EsrafelIoError> {
+        let mut fld = Vec::new();
+        let mut int = Vec::new();
+        let mut report = ImportReport::default();
+        let needed = opts.field_col.unwrap_or(0).max(opts.intensity_col) + 1;
+
+        for (n, line) in content.lines().enumerate() {
+            let skip = |reason| SkippedRow { line: n + 1, reason };
+
+            if n < opts.header_rows {
+                report.skipped.push(skip(SkipReason::Header));
+                continue;
+            }
+
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+
+            if let Some(prefix) = &opts.comment {
+                if !prefix.is_empty() && trimmed.starts_with(prefix.as_str()) {
+                    report.skipped.push(skip(SkipReason::Comment));
+                    continue;
+                }
+            }
+
+            let cols: Vec<&str> = match opts.delimiter {
+                Some(d) => trimmed.split(d).map(|c| c.trim()).collect(),
+                None => trimmed.split_whitespace().collect(),
+            };
+
+            if cols.len() < needed {
+                report.skipped.push(skip(SkipReason::Columns { found: cols.len(), needed }));
+                continue;
+            }
+
+            let number = |column: usize| -> Result<f64, SkipReason> {
+                let token = cols[column];
+                let parsed = if opts.decimal_comma {
+                    token.replace(',', ".").parse()
+                } else {
+                    token.parse()
+                };
+                parsed.map_err(|_| SkipReason::NotNumeric { column: column + 1, token: token.to_string() })
+            };
+
+            let x = match opts.field_col {
+                Some(c) => number(c),
+                None => Ok((report.rows + 1) as f64),
+            };
+
+            match (x, number(opts.intensity_col)) {
+                (Ok(x), Ok(y)) => {
+                    fld.push(x);
+                    int.push(y);
+                    report.rows += 1;
+                }
+                (Err(reason), _) | (_, Err(reason)) => report.skipped.push(skip(reason)),
+            }
+        }
+
+        if int.is_empty() {
+            return Err(EsrafelIoError::UnexpectedEof {
+                line: content.lines().count() + 1,
+                expected: "any data row",
+            });
+        }
+
+        Ok((Spectrum::new(fld, int), report))
+    }
+}
@@ -0,0 +1,56 @@
+// Bruker ESP/WinEPR format, from the older instruments.
+// A text parameter file (.par) goes with the raw intensities (.spc).
+// WinEPR (PC) writes little endian float32, ESP (Aspect/ESP300) big endian int32:
+// the "DOS Format" keyword tells them apart.
+use super::{Acquisition, EsrafelIoError, Spectrum};
+use super::{descriptor, number, text, to_gauss};
+
+impl Spectrum {
+    pub fn from_winepr(par: &str, spc: &[u8]) -> Result<Self, EsrafelIoError> {
+        let map = descriptor(par);
+        let winepr = map.contains_key("DOS");
+
+        let int: Vec<f64> = spc.chunks_exact(4).map(|c| {
+            let bytes = c.try_into().unwrap();
+            if winepr { f32::from_le_bytes(bytes) as f64 } else { i32::from_be_bytes(bytes) as f64 }
+        }).collect();
+
+        // Resolution first, ANZ is the total count for 2D data
+        let points = match number(&map, "RES").or_else(|_| number(&map, "ANZ")) {
+            Ok(p) => p as usize,
+            Err(_) => int.len(),
+        };
+
+        if int.len() != points || !spc.len().is_multiple_of(4) {
+            return Err(EsrafelIoError::BinarySize { expected: points * 4, found: spc.len() });
+        }
+
+        // Center field and sweep width; GST/GSI are the same information as start and width
+        let (start, width) = match (number(&map, "HCF"), number(&map, "HSW")) {
+            (Ok(hcf), Ok(hsw)) => (hcf - hsw / 2.0, hsw),
+            _ => (number(&map, "GST")?, number(&map, "GSI")?),
+        };
+
+        let unit = text(&map, "JUN").unwrap_or("G");
+        let step = if points > 1 { width / (points - 1) as f64 } else { 0.0 };
+        let fld = (0..points).map(|i| to_gauss(start + step * i as f64, unit)).collect();
+
+        let meta = Acquisition {
+            title: text(&map, "JCO").map(String::from),
+            mwfq: number(&map, "MF").ok(),
+            power: number(&map, "MP").ok(),
+            mod_amp: number(&map, "RMA").ok(),  // Always in gauss
+            mod_freq: number(&map, "RMF").ok(),
+            // RRG is a linear gain
+            gain: number(&map, "RRG").ok().filter(|g| *g > 0.0).map(|g| 20.0 * g.log10()),
+            temperature: number(&map, "TE").ok(),
+        };
+
+        Ok(Spectrum {
+            idx: (1..=points).collect(),
+            fld,
+            int,
+            meta,
+        })
+    }
+}
@@ -1,9 +1,20 @@
+pub mod backend;
+pub mod dynamic;
 pub mod eprft;
+pub mod fit;
 pub mod io;
+pub mod powder;
+mod bessel;
+mod complex;
+mod faddeeva;
+mod fft;
+mod hyperfine;
+mod linalg;
+pub use hyperfine::{stick_spectrum, stick_spectrum_at, Line, LinePositions};
 use serde::{Serialize, Deserialize};
-use rand::{thread_rng, Rng};
+use rand::Rng;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Param {
     pub val: f64,  // Value; starts with 0.0
     pub var: f64,  // Variation; starts with: 0.0
@@ -14,9 +25,9 @@ impl Param {
         Param { val, var, }
     }
 
-    pub fn randomize(&self) -> Param {
+    // Seed `rng` to replay the same moves
+    pub fn randomize<G: Rng + ?Sized>(&self, rng: &mut G) -> Param {
         if self.var != 0.0 {
-            let mut rng = thread_rng();
             let random: f64 = rng.gen();  // random number in range [0, 1)
             let rnd = 2.0*random-1.0;
             let new_val = self.val + rnd * self.var;
@@ -44,14 +55,40 @@ impl Nucleus {
     }
 }
 
+// Free electron g-factor
+pub const G_E: f64 = 2.00231930436;
+
+fn free_electron() -> Param {
+    Param::set(G_E, 0.0)
+}
+
+// How Lorentzian and Gaussian broadening combine
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Lineshape {
+    #[default]
+    PseudoVoigt,  // lrtz% Lorentzian plus Gaussian, both lwa wide, like ESR Commander
+    Voigt,  // Convolution of a Gaussian lwa wide and a Lorentzian lwl wide; lrtz is ignored
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Radical {
     pub lwa: Param,  // Line width A
-    // pub lwb: Param,
-    // pub lwc: Param,
+    // Width of each line is A + B·M + C·M², with M the M_I of the first nucleus group
+    #[serde(default)]
+    pub lwb: Param,
+    #[serde(default)]
+    pub lwc: Param,
     pub lrtz: Param,  // Lorentzian linewidth parameter (%)
+    #[serde(default)]
+    pub lwl: Param,  // Lorentzian line width of the Voigt profile
+    #[serde(default)]
+    pub lineshape: Lineshape,
+    #[serde(default)]
+    pub phase: Param,  // Detection phase [deg]: dispersion admixture, for asymmetric lines
     pub amount: Param,  // Relative amount
-    pub dh1: Param,
+    pub dh1: Param,  // Field offset [G]
+    #[serde(default = "free_electron")]
+    pub g: Param,  // Isotropic g-factor; used only when the microwave frequency is known
     pub nucs: Vec<Nucleus>,
 }
 
@@ -59,9 +96,15 @@ impl Radical {
     pub fn set(lwa: f64, lrtz: f64, amount: f64, dh1: f64, nucs: Vec<Nucleus>) -> Self {
         Self {
             lwa: Param::set(lwa, 0.0),
+            lwb: Param::default(),
+            lwc: Param::default(),
             lrtz: Param::set(lrtz, 0.0),
+            lwl: Param::default(),
+            lineshape: Lineshape::default(),
+            phase: Param::default(),
             amount: Param::set(amount, 0.0),
             dh1: Param::set(dh1, 0.0),
+            g: free_electron(),
             nucs,
         }
     }
@@ -97,6 +140,8 @@ pub fn add(left: usize, right: usize) -> usize {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
 
     #[test]
     fn it_works() {
@@ -104,6 +149,789 @@ mod tests {
         assert_eq!(result, 4);
     }
 
+    #[test]
+    fn simulate_on_field_axis() {
+        let rads = vec![Radical::_probe()];
+        let uniform: Vec<f64> = (0..1024).map(|i| 3400.0 + 100.0 * i as f64 / 1023.0).collect();
+        let opts = eprft::SimOptions::default();
+        assert_eq!(eprft::calcola_fld(&rads, &uniform, &opts), eprft::calcola(&rads, 100.0, 1024.0));
+
+        // Slightly irregular steps, same range: almost the same spectrum
+        let mut irregular = uniform.clone();
+        for (i, x) in irregular.iter_mut().enumerate().skip(1).take(1022) {
+            *x += if i % 2 == 0 { 0.01 } else { -0.01 };
+        }
+        let reference = eprft::calcola(&rads, 100.0, 1024.0);
+        let max = reference.iter().fold(0.0_f64, |m, y| m.max(y.abs()));
+        for (a, b) in eprft::calcola_fld(&rads, &irregular, &opts).iter().zip(reference.iter()) {
+            assert!((a - b).abs() < 0.05 * max);
+        }
+    }
+
+    #[test]
+    fn no_jump_between_lineshapes() {
+        // Per line parameters barely off zero: barely another spectrum than `calcola`
+        let mut rad = Radical::_probe();
+        rad.lwa.val = 1.0;
+        rad.lrtz.val = 50.0;
+        let fld: Vec<f64> = (0..1024).map(|i| 3400.0 + 100.0 * i as f64 / 1023.0).collect();
+        let opts = eprft::SimOptions::default();
+        let plain = eprft::calcola_fld(std::slice::from_ref(&rad), &fld, &opts);
+        let max = plain.iter().fold(0.0_f64, |m, y| m.max(y.abs()));
+
+        let mut phased = rad.clone();
+        phased.phase.val = 1e-9;
+        let mut widths = rad.clone();
+        widths.lwb.val = 1e-9;
+        widths.lwc.val = 1e-9;
+        for other in [phased, widths] {
+            let teor = eprft::calcola_fld(&[other], &fld, &opts);
+            assert!(plain.iter().zip(teor.iter()).all(|(a, b)| (a - b).abs() < 1e-6 * max));
+        }
+    }
+
+    #[test]
+    fn radicals_at_their_g_value() {
+        // Two radicals 1 mT apart at 9.5 GHz, with the axis centered between them
+        let mwfq = 9.5;
+        let mut first = Radical::_electron();
+        let mut second = Radical::_electron();
+        first.g.val = 2.0;
+        second.g.val = mwfq * 714.4773 / (eprft::resonance_field(2.0, mwfq) + 10.0);
+
+        let center = eprft::resonance_field(2.0, mwfq) + 5.0;
+        let fld: Vec<f64> = (0..1024).map(|i| center - 50.0 + 100.0 * i as f64 / 1023.0).collect();
+        let opts = eprft::SimOptions { mwfq: Some(mwfq), ..Default::default() };
+
+        // Same as the legacy offsets, -5 G and +5 G from the center
+        let mut legacy = vec![Radical::_electron(), Radical::_electron()];
+        legacy[0].dh1.val = -5.0;
+        legacy[1].dh1.val = 5.0;
+
+        let with_g = eprft::calcola_fld(&[first, second], &fld, &opts);
+        let with_dh1 = eprft::calcola(&legacy, 100.0, 1024.0);
+        for (a, b) in with_g.iter().zip(with_dh1.iter()) {
+            assert!((a - b).abs() < 1e-9);
+        }
+
+        // A random move past zero doesn't send the lines to infinity
+        let mut wild = Radical::_electron();
+        wild.g.val = -0.5;
+        let wild = eprft::check_pars(wild);
+        assert!(wild.g.val > 0.0);
+        assert!(eprft::calcola_fld(&[wild], &fld, &opts).iter().all(|y| y.is_finite()));
+    }
+
+    #[test]
+    fn second_order_positions() {
+        // Nitroxide at X-band: one 14N, a = 15 G
+        let mut rad = Radical::_electron();
+        rad.nucs = vec![Nucleus::set(1.0, 15.0, 1.0)];
+        let b0 = 3390.0;
+
+        let first = stick_spectrum_at(&rad, b0, LinePositions::FirstOrder);
+        let second = stick_spectrum_at(&rad, b0, LinePositions::SecondOrder);
+        let exact = stick_spectrum_at(&rad, b0, LinePositions::BreitRabi);
+        assert_eq!(first.len(), 3);
+        assert_eq!(second.len(), 3);
+
+        for ((f, s), e) in first.iter().zip(second.iter()).zip(exact.iter()) {
+            // Every line moves to lower field, by a²/2B0 [I(I+1) - M²]
+            let m = (b0 - f.field) / 15.0;
+            let expected = 225.0 / (2.0 * b0) * (2.0 - m * m);
+            assert!((f.field - s.field - expected).abs() < 1e-9);
+            // Third order is tiny at X-band
+            assert!((s.field - e.field).abs() < 2e-3);
+            assert_eq!(s.intensity, e.intensity);
+        }
+
+        // Two equivalent protons: F = 1 triplet plus F = 0 singlet, 1:2:1 overall
+        rad.nucs = vec![Nucleus::set(0.5, 20.0, 2.0)];
+        let lines = stick_spectrum_at(&rad, b0, LinePositions::SecondOrder);
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines.iter().map(|l| l.intensity).sum::<f64>(), 4.0);
+        assert!(lines.iter().any(|l| l.field == b0));
+    }
+
+    #[test]
+    fn m_i_dependent_linewidths() {
+        let mut rad = Radical::_electron();
+        rad.lwa.val = 1.0;
+        rad.nucs = vec![Nucleus::set(1.0, 15.0, 1.0)];
+        let fld: Vec<f64> = (0..2048).map(|i| 3350.0 + 80.0 * i as f64 / 2047.0).collect();
+        let opts = eprft::SimOptions::default();
+
+        // Peak to peak height of the three lines, from low to high field,
+        // up to the sampling of the field axis
+        let heights = |rad: &Radical| -> Vec<f64> {
+            let teor = eprft::calcola_fld(std::slice::from_ref(rad), &fld, &opts);
+            teor.chunks(2048 / 3 + 1).map(|c| {
+                let max = c.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+                let min = c.iter().cloned().fold(f64::INFINITY, f64::min);
+                max - min
+            }).collect()
+        };
+
+        // Sticks keep their M_I: M = +1 sits at low field, M = -1 at high field
+        let lines = stick_spectrum_at(&rad, 3390.0, LinePositions::FirstOrder);
+        assert_eq!(lines.iter().map(|l| l.m_i[0]).collect::<Vec<_>>(), vec![-1.0, 0.0, 1.0]);
+        assert!(lines[0].field > lines[2].field);
+
+        let even = heights(&rad);
+        assert!((even[0] - even[2]).abs() < 1e-2 * even[1]);
+
+        // Broader lines are lower; C broadens both wings the same way
+        rad.lwb.val = 0.2;
+        let skewed = heights(&rad);
+        assert!(skewed[0] < skewed[1] && skewed[1] < skewed[2]);
+
+        rad.lwb.val = 0.0;
+        rad.lwc.val = 0.3;
+        let wings = heights(&rad);
+        assert!((wings[0] - wings[2]).abs() < 1e-2 * wings[1]);
+        assert!(wings[0] < wings[1]);
+
+        // An empty first group still owns m_i[0], so B and C see M = 0
+        rad.nucs.insert(0, Nucleus::set(1.0, 15.0, 0.0));
+        let lines = stick_spectrum_at(&rad, 3390.0, LinePositions::FirstOrder);
+        assert!(lines.iter().all(|l| l.m_i.len() == 2 && l.m_i[0] == 0.0));
+        assert_eq!(lines.iter().map(|l| l.m_i[1]).collect::<Vec<_>>(), vec![-1.0, 0.0, 1.0]);
+        let flat = heights(&rad);
+        assert!((flat[0] - flat[2]).abs() < 1e-2 * flat[1]);
+    }
+
+    #[test]
+    fn powder_patterns() {
+        let mwfq = 9.5;
+        let fld: Vec<f64> = (0..1024).map(|i| 3280.0 + 160.0 * i as f64 / 1023.0).collect();
+        let sim = eprft::SimOptions { mwfq: Some(mwfq), ..Default::default() };
+        let grid = powder::PowderOptions::default();
+
+        // Isotropic tensors give the isotropic spectrum, offset included
+        let mut rad = Radical::_electron();
+        rad.lwa.val = 1.0;
+        rad.g.val = 2.0023;
+        rad.dh1.val = 3.0;
+        let iso = powder::calcola(&[powder::PowderRadical::from(&rad)], &fld, &sim, &grid);
+        let direct = eprft::SimOptions { positions: LinePositions::SecondOrder, ..sim.clone() };
+        let reference = eprft::calcola_fld(&[rad.clone()], &fld, &direct);
+        for (a, b) in iso.iter().zip(reference.iter()) {
+            assert!((a - b).abs() < 1e-9 * reference.iter().cloned().fold(0.0, f64::max));
+        }
+
+        // Axial g: the strong feature sits at g perpendicular, a dip at g parallel
+        let mut axial = powder::PowderRadical::from(&rad);
+        axial.g = powder::Tensor::axial(2.05, 2.0);
+        axial.dh1.val = 0.0;
+        let teor = powder::calcola(&[axial], &fld, &sim, &grid);
+        let (top, _) = teor.iter().enumerate().fold((0, f64::MIN), |m, (i, v)| if *v > m.1 { (i, *v) } else { m });
+        assert!((fld[top] - eprft::resonance_field(2.05, mwfq)).abs() < 2.0);
+
+        let parallel = fld.iter().position(|x| *x >= eprft::resonance_field(2.0, mwfq) + 0.5).unwrap();
+        assert!(teor[parallel] < 0.0);
+    }
+
+    #[test]
+    fn exact_stick_spectrum() {
+        // Three equivalent 14N and two groups of protons
+        let mut rad = Radical::_electron();
+        rad.dh1.val = 2.0;
+        rad.nucs = vec![
+            Nucleus::set(1.0, 7.0, 3.0),
+            Nucleus::set(0.5, 2.5, 2.0),
+            Nucleus::set(0.5, 0.0, 1.0),
+        ];
+
+        let lines = stick_spectrum(&rad);
+        assert_eq!(lines.len(), 7 * 3 * 2);
+        assert_eq!(lines.iter().map(|l| l.intensity).sum::<f64>(), 27.0 * 4.0 * 2.0);
+
+        // 1:3:6:7:6:3:1 for three spin 1
+        let central: Vec<f64> = lines.iter()
+            .filter(|l| l.m_i[1] == 0.0 && l.m_i[2] == 0.5)
+            .map(|l| l.intensity / 2.0)
+            .collect();
+        assert_eq!(central, vec![1.0, 3.0, 6.0, 7.0, 6.0, 3.0, 1.0]);
+
+        for line in &lines {
+            let shift: f64 = line.m_i.iter().zip(rad.nucs.iter()).map(|(m, nuc)| m * nuc.hpf.val).sum();
+            assert!((line.field - (2.0 - shift)).abs() < 1e-12);
+        }
+
+        // Large spins and many equivalents don't overflow anything
+        rad.nucs = vec![Nucleus::set(3.5, 1.0, 12.0)];
+        let lines = stick_spectrum(&rad);
+        assert_eq!(lines.len(), 85);
+        assert_eq!(lines.iter().map(|l| l.intensity).sum::<f64>(), 8f64.powi(12));
+    }
+
+    #[test]
+    fn output_modes() {
+        let mut rad = Radical::_electron();
+        rad.lwa.val = 2.0;
+        rad.lrtz.val = 30.0;
+        rad.nucs = vec![Nucleus::set(0.5, 8.0, 1.0)];
+        let step = 0.01;
+        let fld: Vec<f64> = (0..40001).map(|i| 3190.0 + step * i as f64).collect();
+        // Second order lines are binned the same way for every mode
+        let sim = |output| {
+            let opts = eprft::SimOptions { output, positions: LinePositions::SecondOrder, ..Default::default() };
+            eprft::calcola_fld(std::slice::from_ref(&rad), &fld, &opts)
+        };
+
+        let abs = sim(eprft::OutputMode::Absorption);
+        let first = sim(eprft::OutputMode::FirstDerivative);
+        let second = sim(eprft::OutputMode::SecondDerivative);
+
+        // Each mode is the derivative of the previous one
+        let max = first.iter().map(|v| v.abs()).fold(0.0, f64::max);
+        for i in 1..fld.len() - 1 {
+            assert!(((abs[i + 1] - abs[i - 1]) / (2.0 * step) - first[i]).abs() < 1e-3 * max);
+        }
+        let max = second.iter().map(|v| v.abs()).fold(0.0, f64::max);
+        for i in 1..fld.len() - 1 {
+            assert!(((first[i + 1] - first[i - 1]) / (2.0 * step) - second[i]).abs() < 1e-3 * max);
+        }
+
+        // Absorption area is the amount, up to the Lorentzian tails out of the axis
+        let area: f64 = abs.iter().sum::<f64>() * step;
+        assert!((area - rad.amount.val).abs() < 0.01 * rad.amount.val);
+    }
+
+    #[test]
+    fn voigt_lineshape() {
+        // Known values of the Faddeeva function
+        let w = faddeeva::w(complex::Complex::new(1.0, 1.0));
+        assert!((w.re - 0.3047442052569126).abs() < 1e-4 && (w.im - 0.2082189382028316).abs() < 1e-4);
+        let w = faddeeva::w(complex::Complex::new(0.5, 0.0));
+        assert!((w.re - (-0.25f64).exp()).abs() < 1e-4);
+
+        let mut rad = Radical::_electron();
+        rad.lwa.val = 1.5;
+        rad.lrtz.val = 0.0;
+        let fld: Vec<f64> = (0..2001).map(|i| 3340.0 + 0.05 * i as f64).collect();
+        let opts = eprft::SimOptions { positions: LinePositions::SecondOrder, ..Default::default() };
+        let gauss = eprft::calcola_fld(std::slice::from_ref(&rad), &fld, &opts);
+
+        // Without Lorentzian broadening, a Voigt is the Gaussian
+        rad.lineshape = Lineshape::Voigt;
+        let voigt = eprft::calcola_fld(std::slice::from_ref(&rad), &fld, &opts);
+        let max = gauss.iter().cloned().fold(0.0, f64::max);
+        for (a, b) in gauss.iter().zip(voigt.iter()) {
+            assert!((a - b).abs() < 1e-3 * max);
+        }
+
+        // Adding a Lorentzian broadens the line: peaks drift apart and get lower
+        rad.lwl.val = 1.0;
+        let broad = eprft::calcola_fld(std::slice::from_ref(&rad), &fld, &opts);
+        let peak = |v: &[f64]| v.iter().enumerate().fold((0, f64::MIN), |m, (i, x)| if *x > m.1 { (i, *x) } else { m });
+        let (narrow_at, narrow_max) = peak(&voigt);
+        let (broad_at, broad_max) = peak(&broad);
+        assert!(broad_at < narrow_at && broad_max < narrow_max);
+    }
+
+    #[test]
+    fn binned_lines() {
+        // Binned and convolved, lines off the grid match their direct sum
+        let mut rad = Radical::_electron();
+        rad.lwa.val = 0.8;
+        rad.lwb.val = 0.1;
+        rad.lrtz.val = 40.0;
+        rad.dh1.val = 0.37;
+        rad.nucs = vec![Nucleus::set(1.0, 13.7, 2.0), Nucleus::set(0.5, 3.1, 4.0)];
+        let mwfq = 9.5;
+        let fld: Vec<f64> = (0..2048).map(|i| 3320.0 + 0.05 * i as f64).collect();
+        let opts = eprft::SimOptions { mwfq: Some(mwfq), positions: LinePositions::SecondOrder, ..Default::default() };
+        let binned = eprft::calcola_fld(std::slice::from_ref(&rad), &fld, &opts);
+
+        let b0 = eprft::resonance_field(rad.g.val, mwfq) + rad.dh1.val;
+        let lines = stick_spectrum_at(&rad, b0, LinePositions::SecondOrder);
+        let totale: f64 = lines.iter().map(|l| l.intensity).sum();
+        let mut direct = vec![0.0; fld.len()];
+        for line in &lines {
+            let shape = eprft::Shape::of(&rad, eprft::linewidth(&rad, &line.m_i), totale);
+            for (y, x) in direct.iter_mut().zip(fld.iter()) {
+                *y += line.intensity * shape.at(x - line.field, opts.output);
+            }
+        }
+
+        let max = direct.iter().fold(0.0_f64, |m, y| m.max(y.abs()));
+        for (a, b) in binned.iter().zip(direct.iter()) {
+            assert!((a - b).abs() < 1e-2 * max);
+        }
+    }
+
+    #[test]
+    fn fft_convolution() {
+        // Both ways of spreading the lineshape over the sticks agree, odd and even lengths
+        for points in [1023, 4096] {
+            let mut rng = StdRng::seed_from_u64(points as u64);
+            let intensity: Vec<f64> = (0..points).map(|i| if i % 7 == 3 { rng.gen() } else { 0.0 }).collect();
+            let lno: Vec<f64> = (0..points).map(|_| rng.gen::<f64>() - 0.5).collect();
+
+            let mut direct = vec![0.0; points];
+            let mut fft = vec![0.0; points];
+            eprft::convolve_sticks_direct(&intensity, &lno, &mut direct);
+            eprft::convolve_sticks_fft(&intensity, &lno, &mut fft);
+
+            let max = direct.iter().fold(0.0_f64, |m, x| m.max(x.abs()));
+            assert!(direct.iter().zip(fft.iter()).all(|(a, b)| (a - b).abs() < 1e-10 * max));
+        }
+    }
+
+    // The timings of docs/eprft.org and the FFT_COST they give; slow, run it in release
+    #[test]
+    #[ignore]
+    fn convolution_timings() {
+        use std::time::Instant;
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut costs = Vec::new();
+        println!("| points | sticks | direct [ms] | FFT [ms] |");
+        for points in [4096, 8192] {
+            let lno: Vec<f64> = (0..points).map(|_| rng.gen::<f64>() - 0.5).collect();
+            for sticks in [50, 200, 1000] {
+                let mut intensity = vec![0.0; points];
+                for k in 0..sticks {
+                    intensity[1 + k * (points - 1) / sticks] = rng.gen();
+                }
+
+                // Best of a few runs, in ms
+                let time = |convolve: fn(&[f64], &[f64], &mut [f64])| {
+                    (0..20).map(|_| {
+                        let mut out = vec![0.0; points];
+                        let start = Instant::now();
+                        convolve(&intensity, &lno, &mut out);
+                        start.elapsed().as_secs_f64() * 1e3
+                    }).fold(f64::INFINITY, f64::min)
+                };
+                let direct = time(eprft::convolve_sticks_direct);
+                let fft = time(eprft::convolve_sticks_fft);
+                println!("| {:6} | {:6} | {:11.3} | {:8.3} |", points, sticks, direct, fft);
+
+                // FFT time in multiply-adds of the direct sum, per n·log2(n)
+                let n = (2 * points).next_power_of_two();
+                let madd = direct / (sticks * points) as f64;
+                costs.push(fft / madd / (n * n.trailing_zeros() as usize) as f64);
+            }
+        }
+        let mean = costs.iter().sum::<f64>() / costs.len() as f64;
+        println!("FFT_COST measured {:.1}, used {}", mean, eprft::FFT_COST);
+
+        // Off-grid lines, binned, against their direct sum
+        let mut rad = Radical::_electron();
+        rad.nucs = vec![Nucleus::set(0.5, 2.3, 6.0), Nucleus::set(1.0, 7.1, 3.0)];
+        let opts = eprft::SimOptions { positions: LinePositions::SecondOrder, ..Default::default() };
+        for points in [4096, 8192] {
+            let fld: Vec<f64> = (0..points).map(|i| 3300.0 + 80.0 * i as f64 / points as f64).collect();
+            let start = Instant::now();
+            eprft::calcola_fld(std::slice::from_ref(&rad), &fld, &opts);
+            let binned = start.elapsed().as_secs_f64() * 1e3;
+
+            let lines = stick_spectrum_at(&rad, 3340.0, LinePositions::SecondOrder);
+            let shape = eprft::Shape::of(&rad, rad.lwa.val, 1.0);
+            let start = Instant::now();
+            let _: Vec<f64> = fld.iter().map(|x| lines.iter().map(|l| l.intensity * shape.at(x - l.field, opts.output)).sum()).collect();
+            let direct = start.elapsed().as_secs_f64() * 1e3;
+            println!("{} points, {} lines: binned {:.3} ms, direct {:.3} ms", points, lines.len(), binned, direct);
+        }
+    }
+
+    #[test]
+    fn field_modulation() {
+        for (n, x, j) in [(0, 1.0, 0.7651976865579666), (1, 1.0, 0.4400505857449335),
+                          (0, 2.404825557695773, 0.0), (1, 3.8317059702075125, 0.0),
+                          (0, 30.634606468431975, 0.0), (1, -1.0, -0.4400505857449335)] {
+            assert!((bessel::jn(n, x) - j).abs() < 1e-8, "J{}({})", n, x);
+        }
+
+        let mut rad = Radical::_electron();
+        rad.lwa.val = 2.0;
+        let fld: Vec<f64> = (0..1001).map(|i| 3320.0 + 0.04 * i as f64).collect();
+        let opts = eprft::SimOptions { positions: LinePositions::SecondOrder, ..Default::default() };
+        let derivative = eprft::calcola_fld(std::slice::from_ref(&rad), &fld, &opts);
+
+        // A small amplitude gives back the derivatives
+        for (harmonic, output) in [(1, eprft::OutputMode::FirstDerivative), (2, eprft::OutputMode::SecondDerivative)] {
+            let plain = eprft::SimOptions { output, ..opts.clone() };
+            let expected = eprft::calcola_fld(std::slice::from_ref(&rad), &fld, &plain);
+            let weak = eprft::SimOptions {
+                modulation: Some(eprft::Modulation { amplitude: 0.05, harmonic }),
+                ..opts.clone()
+            };
+            let modulated = eprft::calcola_fld(std::slice::from_ref(&rad), &fld, &weak);
+            let max = expected.iter().fold(0.0_f64, |m, y| m.max(y.abs()));
+            for (a, b) in expected.iter().zip(modulated.iter()) {
+                assert!((a - b).abs() < 1e-2 * max);
+            }
+        }
+
+        // Overmodulation spreads the peaks apart
+        let peaks = |v: &[f64]| {
+            let top = v.iter().enumerate().fold((0, f64::MIN), |m, (i, y)| if *y > m.1 { (i, *y) } else { m });
+            let bottom = v.iter().enumerate().fold((0, f64::MAX), |m, (i, y)| if *y < m.1 { (i, *y) } else { m });
+            fld[bottom.0] - fld[top.0]
+        };
+        let strong = eprft::SimOptions { modulation: Some(eprft::Modulation::new(6.0)), ..opts };
+        let broad = eprft::calcola_fld(std::slice::from_ref(&rad), &fld, &strong);
+        assert!(peaks(&broad) > peaks(&derivative) + 1.0);
+    }
+
+    #[test]
+    fn dispersion_phase() {
+        let fld: Vec<f64> = (0..2001).map(|i| 3330.0 + 0.01 * i as f64).collect();
+        let opts = eprft::SimOptions {
+            positions: LinePositions::SecondOrder,
+            output: eprft::OutputMode::Absorption,
+            ..Default::default()
+        };
+        let peak = |v: &[f64]| fld[v.iter().enumerate().fold((0, f64::MIN), |m, (i, y)| if *y > m.1 { (i, *y) } else { m }).0];
+
+        // Dispersion peaks at 1/tl from the center for a Lorentzian,
+        // and where Dawson's function peaks for a Gaussian
+        let mut rad = Radical::_electron();
+        rad.lwa.val = 2.0;
+        rad.phase.val = 90.0;
+        for (lrtz, offset) in [(100.0, 3.0_f64.sqrt() / 4.0), (0.0, 0.9241388730 * std::f64::consts::SQRT_2)] {
+            rad.lrtz.val = lrtz;
+            let dispersion = eprft::calcola_fld(std::slice::from_ref(&rad), &fld, &opts);
+            assert!((peak(&dispersion) - 3340.0 - offset).abs() < 0.02);
+            // Odd around the center
+            let max = dispersion.iter().fold(0.0_f64, |m, y| m.max(y.abs()));
+            assert!(dispersion[1000].abs() < 1e-3 * max);
+            assert!((dispersion[700] + dispersion[1300]).abs() < 1e-3 * max);
+        }
+
+        // Half a turn flips the line, for both lineshapes
+        rad.lrtz.val = 50.0;
+        for lineshape in [Lineshape::PseudoVoigt, Lineshape::Voigt] {
+            rad.lineshape = lineshape;
+            rad.lwl.val = 0.5;
+            rad.phase.val = 0.0;
+            let straight = eprft::calcola_fld(std::slice::from_ref(&rad), &fld, &opts);
+            rad.phase.val = 180.0;
+            let flipped = eprft::calcola_fld(std::slice::from_ref(&rad), &fld, &opts);
+            assert!(straight.iter().zip(flipped.iter()).all(|(a, b)| (a + b).abs() < 1e-12));
+        }
+    }
+
+    #[test]
+    fn fit_with_baseline() {
+        let teor = eprft::calcola(&vec![Radical::_electron()], 100.0, 1024.0);
+        let x = |i: usize| 2.0 * i as f64 / 1023.0 - 1.0;
+        let exp: Vec<f64> = teor.iter().enumerate().map(|(i, y)| 3.0 * y + 0.2 + 0.1 * x(i)).collect();
+
+        let fit = eprft::fit_baseline(&exp, &teor, 1).unwrap();
+        assert!((fit.norma - 3.0).abs() < 1e-9);
+        assert!((fit.coeffs[0] - 0.2).abs() < 1e-9 && (fit.coeffs[1] - 0.1).abs() < 1e-9);
+
+        // The drift doesn't end up in sigma anymore
+        let (sigma, _) = eprft::errore(&exp, 1024.0, teor.clone());
+        let (with_baseline, fitted) = eprft::errore_with(&exp, teor, eprft::Baseline::Polynomial(2));
+        assert!(with_baseline < 1e-9 && sigma > 0.05);
+        assert!((fitted[500] - exp[500]).abs() < 1e-9);
+    }
+
+    #[test]
+    fn simulated_annealing() {
+        let fld: Vec<f64> = (0..512).map(|i| 3330.0 + 40.0 * i as f64 / 511.0).collect();
+        let opts = eprft::SimOptions::default();
+        let mut target = Radical::_electron();
+        target.lwa.val = 0.8;
+        let exp = eprft::calcola_fld(&[target], &fld, &opts);
+
+        let mut start = Radical::_electron();
+        start.lwa = Param::set(2.0, 0.1);
+        let schedule = eprft::Annealing { reheat_after: 100, ..Default::default() };
+        let mut state = eprft::AnnealState::new(vec![start], 1e20, &schedule);
+        let mut rng = StdRng::seed_from_u64(1);
+
+        let mut uphill = false;
+        for _ in 0..800 {
+            let (sigma, best_sigma) = (state.sigma, state.best_sigma);
+            state = eprft::anneal(&exp, &fld, &opts, &schedule, state, &mut rng).0;
+            uphill |= state.sigma > sigma;
+            assert!(state.best_sigma <= best_sigma);
+        }
+
+        // It went uphill on the way, but kept the best
+        assert!(uphill);
+        assert!((state.best[0].lwa.val - 0.8).abs() < 0.05);
+        assert!(state.temperature < schedule.temperature);
+    }
+
+    #[test]
+    fn levenberg_marquardt() {
+        let fld: Vec<f64> = (0..1024).map(|i| 3310.0 + 80.0 * i as f64 / 1023.0).collect();
+        let opts = eprft::SimOptions::default();
+        let mut target = Radical::_probe();
+        target.lwa.val = 0.8;
+        target.lrtz.val = 60.0;
+        target.dh1.val = 0.3;
+        target.nucs[0].hpf.val = 14.5;
+        let exp = eprft::calcola_smooth(std::slice::from_ref(&target), &fld, &opts);
+
+        // Where a Monte Carlo run could have stopped; the amount of a lone radical doesn't matter
+        let mut start = Radical::_probe();
+        start.lwa = Param::set(0.85, 0.1);
+        start.lrtz = Param::set(65.0, 5.0);
+        start.dh1 = Param::set(0.25, 0.1);
+        start.amount.var = 1.0;
+        start.nucs[0].hpf = Param::set(14.45, 0.5);
+
+        let refined = fit::levenberg_marquardt(&exp, &fld, &opts, vec![start], &fit::LevMar::default());
+        let rad = &refined.rads[0];
+        assert!(refined.converged && refined.iters < 50);
+        assert!(refined.sigma < 1e-6);
+        assert!((rad.lwa.val - 0.8).abs() < 1e-4 && (rad.lrtz.val - 60.0).abs() < 1e-2);
+        assert!((rad.dh1.val - 0.3).abs() < 1e-4 && (rad.nucs[0].hpf.val - 14.5).abs() < 1e-4);
+        assert_eq!(rad.amount.val, 100.0);
+    }
+
+    #[test]
+    fn fit_uncertainties() {
+        let fld: Vec<f64> = (0..1024).map(|i| 3310.0 + 80.0 * i as f64 / 1023.0).collect();
+        let opts = eprft::SimOptions::default();
+        let mut target = Radical::_probe();
+        target.lrtz.val = 60.0;
+        let clean = eprft::calcola_smooth(std::slice::from_ref(&target), &fld, &opts);
+        let peak = clean.iter().fold(0.0_f64, |m, y| m.max(y.abs()));
+
+        // Same pseudo-random noise, at two levels
+        let noisy = |level: f64| -> Vec<f64> {
+            clean.iter().enumerate().map(|(i, y)| y + level * peak * ((i * i) as f64 * 0.7).sin()).collect()
+        };
+
+        let mut start = target.clone();
+        start.lwa.var = 0.1;
+        start.lrtz.var = 5.0;
+        start.nucs[0].hpf.var = 0.5;
+        start.amount.var = 1.0;
+
+        let report = |exp: &[f64]| {
+            let refined = fit::levenberg_marquardt(exp, &fld, &opts, vec![start.clone()], &fit::LevMar::default());
+            fit::uncertainties(exp, &fld, &opts, &refined.rads).unwrap()
+        };
+        let low = report(&noisy(0.01));
+        let high = report(&noisy(0.02));
+
+        assert_eq!(low.names, ["rad 1 lwa", "rad 1 lrtz", "rad 1 amount", "rad 1 hpf 1"]);
+        // The amount of a lone radical is lost in the normalization
+        assert!(low.errors[2].is_infinite() && low.correlation[0][2].is_nan());
+
+        for k in [0, 1, 3] {
+            assert!(low.errors[k] > 0.0 && low.errors[k].is_finite());
+            assert!((high.errors[k] / low.errors[k] - 2.0).abs() < 0.1);
+            assert!((low.values[k] - fit::get_varied(&[start.clone()], false)[k]).abs() < 5.0 * low.errors[k]);
+        }
+        assert!((low.correlation[0][1] - low.correlation[1][0]).abs() < 1e-12);
+        assert!(low.correlation[0][1].abs() <= 1.0);
+
+        assert!(low.strongly_correlated().is_empty());
+
+        // Where the simplex stops, the Jacobian sees a minimum too: same spectrum
+        let exp = noisy(0.01);
+        let mut simplex = fit::Simplex::new(&exp, &fld, &opts, vec![start.clone()]);
+        while !simplex.converged() {
+            fit::nelder_mead(&exp, &fld, &opts, &mut simplex);
+        }
+        let (res, _) = fit::residuals(&exp, &fld, &opts, &simplex.rads());
+        let norm = |v: &[f64]| v.iter().map(|x| x * x).sum::<f64>().sqrt();
+        let jac = fit::jacobian(&exp, &fld, &opts, &simplex.rads());
+        let largest = jac.iter().map(|column| norm(column)).fold(0.0, f64::max);
+        // Skipping the amount, which doesn't change the spectrum
+        for column in jac.iter().filter(|column| norm(column) > 1e-6 * largest) {
+            let gradient: f64 = column.iter().zip(res.iter()).map(|(j, r)| j * r).sum();
+            assert!(gradient.abs() < 1e-3 * norm(column) * norm(&res));
+        }
+
+        // Two singlets almost on top of each other: everything trades for everything
+        let mut pair = vec![Radical::_electron(), Radical::_electron()];
+        pair[0].lwa = Param::set(1.0, 0.1);
+        pair[0].dh1 = Param::set(-0.05, 0.1);
+        pair[1].lwa = Param::set(1.1, 0.1);
+        pair[1].dh1 = Param::set(0.05, 0.1);
+        pair[1].amount.var = 1.0;
+        let clean = eprft::calcola_smooth(&pair, &fld, &opts);
+        let exp: Vec<f64> = clean.iter().enumerate().map(|(i, y)| y + 1e-3 * ((i * i) as f64 * 0.7).sin()).collect();
+        let overlap = fit::uncertainties(&exp, &fld, &opts, &pair).unwrap();
+        let flagged = overlap.strongly_correlated();
+        assert!(flagged.iter().any(|(i, j, r)| (*i, *j) == (2, 3) && *r < -0.9));
+        assert!(flagged.iter().all(|(i, j, _)| i < j));
+
+        // Every amount varied: the first one is kept, the others are relative to it
+        pair[0].amount.var = 1.0;
+        let amounts = fit::uncertainties(&exp, &fld, &opts, &pair).unwrap();
+        assert_eq!(amounts.names[1], "rad 1 amount");
+        assert_eq!(amounts.names[4], "rad 2 amount");
+        assert!(amounts.errors[1].is_infinite() && amounts.correlation[1][4].is_nan());
+        for (k, err) in amounts.errors.iter().enumerate().filter(|(k, _)| *k != 1) {
+            assert!(err.is_finite() && *err > 0.0, "{}", amounts.names[k]);
+        }
+        assert!(amounts.correlation.iter().flatten().all(|r| r.is_nan() || r.abs() <= 1.0 + 1e-12));
+    }
+
+    #[test]
+    fn nelder_mead() {
+        let fld: Vec<f64> = (0..1024).map(|i| 3340.0 + 40.0 * i as f64 / 1023.0).collect();
+        let opts = eprft::SimOptions::default();
+        let mut target = Radical::_electron();
+        target.lwa.val = 0.6;
+        target.nucs.push(Nucleus::set(0.5, 5.0, 2.0));
+        let exp = eprft::calcola_smooth(&[target], &fld, &opts);
+
+        // One proton instead of two, and lrtz pushing against its upper bound
+        let mut start = Radical::_electron();
+        start.lwa = Param::set(0.8, 0.2);
+        start.lrtz.var = 10.0;
+        let mut nuc = Nucleus::set(0.5, 4.5, 1.0);
+        nuc.hpf.var = 1.0;
+        nuc.eqs.var = 1.0;
+        start.nucs.push(nuc);
+
+        let mut simplex = fit::Simplex::new(&exp, &fld, &opts, vec![start]);
+        let mut last = f64::INFINITY;
+        for _ in 0..2000 {
+            if simplex.converged() {
+                break;
+            }
+            let (sigma, _, _) = fit::nelder_mead(&exp, &fld, &opts, &mut simplex);
+            assert!(sigma <= last);
+            last = sigma;
+        }
+
+        let (sigma, teor, rads) = fit::nelder_mead(&exp, &fld, &opts, &mut simplex);
+        assert!(simplex.converged() && sigma < 1e-3);
+        assert_eq!(teor.len(), fld.len());
+        assert_eq!(rads[0].nucs[0].eqs.val, 2.0);
+        assert!((rads[0].nucs[0].hpf.val - 5.0).abs() < 0.05 && (rads[0].lwa.val - 0.6).abs() < 0.01);
+        assert!(rads[0].lrtz.val <= 100.0);
+    }
+
+    #[test]
+    fn shared_dispatch() {
+        use backend::{Model, SimulationMethod, SpectrumModel};
+
+        let fld: Vec<f64> = (0..512).map(|i| 3330.0 + 40.0 * i as f64 / 511.0).collect();
+        let opts = eprft::SimOptions::default();
+        let exp = eprft::calcola_fld(&[Radical::_electron()], &fld, &opts);
+
+        let mut start = Radical::_electron();
+        start.lwa = Param::set(1.0, 0.1);
+        let models = [
+            Model::Isotropic(vec![start.clone()]),
+            Model::Dynamic(vec![dynamic::ExchangeRadical::from(&start)]),
+            Model::Powder(backend::Powder {
+                rads: vec![powder::PowderRadical::from(&start)],
+                opts: powder::PowderOptions { knots: 5 },
+            }),
+        ];
+        assert_eq!(models[0].simulate(&fld, &opts), eprft::calcola_fld(&[start.clone()], &fld, &opts));
+
+        let methods = [
+            SimulationMethod::MC199,
+            SimulationMethod::Dynamic1999,
+            SimulationMethod::Powder,
+            SimulationMethod::Annealing,
+            SimulationMethod::NelderMead,
+        ];
+        for method in methods {
+            let model = match method {
+                SimulationMethod::Dynamic1999 => models[1].clone(),
+                SimulationMethod::Powder => models[2].clone(),
+                _ => models[0].clone(),
+            };
+            let mut optimizer = method.optimizer(model, 1e20, &eprft::Annealing::default(), 7, &exp, &fld, &opts);
+            let first = { optimizer.step(&exp, &fld, &opts); optimizer.best_sigma() };
+            for _ in 0..50 {
+                assert_eq!(optimizer.step(&exp, &fld, &opts).len(), fld.len());
+            }
+            assert!(optimizer.best_sigma() < first, "{:?}", method);
+
+            // Same kind of radicals back
+            let same = matches!(
+                (method, optimizer.best()),
+                (SimulationMethod::Dynamic1999, Model::Dynamic(_)) | (SimulationMethod::Powder, Model::Powder(_))
+            ) || method.is_isotropic() && matches!(optimizer.best(), Model::Isotropic(_));
+            assert!(same, "{:?}", method);
+        }
+    }
+
+    #[test]
+    fn reproducible_runs() {
+        use backend::{Model, SimulationMethod};
+
+        let fld: Vec<f64> = (0..256).map(|i| 3330.0 + 40.0 * i as f64 / 255.0).collect();
+        let opts = eprft::SimOptions::default();
+        let exp = eprft::calcola_fld(&[Radical::_electron()], &fld, &opts);
+
+        let mut start = Radical::_electron();
+        start.lwa = Param::set(1.0, 0.1);
+        start.g = Param::set(2.0, 0.001);
+        let walk = |method: SimulationMethod, model: Model, seed: u64| {
+            let mut optimizer = method.optimizer(model, 1e20, &eprft::Annealing::default(), seed, &exp, &fld, &opts);
+            let sigmas: Vec<f64> = (0..30).map(|_| { optimizer.step(&exp, &fld, &opts); optimizer.best_sigma() }).collect();
+            sigmas
+        };
+        let run = |method, seed| walk(method, Model::Isotropic(vec![start.clone()]), seed);
+
+        // Same seed, same walk; another seed, another walk
+        for method in [SimulationMethod::MC199, SimulationMethod::Annealing] {
+            assert_eq!(run(method, 42), run(method, 42), "{:?}", method);
+            assert_ne!(run(method, 42), run(method, 43), "{:?}", method);
+        }
+
+        // A saved start replays the run
+        let saved = serde_json::to_string(&Model::Isotropic(vec![start.clone()])).unwrap();
+        let loaded: Model = serde_json::from_str(&saved).unwrap();
+        assert_eq!(walk(SimulationMethod::MC199, loaded, 42), run(SimulationMethod::MC199, 42));
+
+        let param = Param::set(1.0, 0.5);
+        let draw = |seed| { let mut rng = StdRng::seed_from_u64(seed); (0..5).map(|_| param.randomize(&mut rng).val).collect::<Vec<_>>() };
+        assert_eq!(draw(3), draw(3));
+    }
+
+    #[test]
+    fn two_site_exchange() {
+        // Two protons swapping 5 and 1 G: (+½, -½) lines jump between -2 and +2 G,
+        // (+½, +½) and (-½, -½) stay at ±3 G
+        let mut rad = dynamic::ExchangeRadical::from(&Radical::_electron());
+        rad.lwa.val = 0.2;
+        for (a, b) in [(5.0, 1.0), (1.0, 5.0)] {
+            rad.nucs.push(dynamic::ExchangeNucleus {
+                spin: Param::set(0.5, 0.0),
+                eqs: Param::set(1.0, 0.0),
+                hpf_a: Param::set(a, 0.0),
+                hpf_b: Param::set(b, 0.0),
+            });
+        }
+
+        let fld: Vec<f64> = (0..1201).map(|i| 3340.0 + 0.01 * i as f64).collect();
+        let at = |v: &[f64], x: f64| v[((x + 6.0) / 0.01).round() as usize];
+        let sim = eprft::SimOptions { output: eprft::OutputMode::Absorption, ..Default::default() };
+
+        // Slow: four lines of the same height
+        rad.rate.val = 0.0;
+        let slow = dynamic::calcola(std::slice::from_ref(&rad), &fld, &sim);
+        assert!((at(&slow, 2.0) / at(&slow, 3.0) - 1.0).abs() < 1e-2);
+
+        // Fast: mixed lines collapse in the center, twice as high as the outer ones
+        rad.rate.val = 1e12;
+        let fast = dynamic::calcola(std::slice::from_ref(&rad), &fld, &sim);
+        assert!((at(&fast, 0.0) / at(&fast, 3.0) - 2.0).abs() < 2e-2);
+        assert!(at(&fast, 2.0) < 0.05 * at(&fast, 3.0));
+
+        // In between, only the mixed lines broaden
+        rad.rate.val = 3e7;
+        let mid = dynamic::calcola(std::slice::from_ref(&rad), &fld, &sim);
+        assert!((at(&mid, 3.0) / at(&slow, 3.0) - 1.0).abs() < 5e-2);
+        assert!(at(&mid, 2.0) < 0.5 * at(&slow, 2.0) && at(&mid, 0.0) < 0.5 * at(&fast, 0.0));
+
+        // Areas don't depend on the rate
+        let area = |v: &[f64]| v.iter().sum::<f64>();
+        assert!((area(&fast) / area(&slow) - 1.0).abs() < 2e-2);
+    }
+
     #[test]
     fn load_spectrum() {
         // Extract from real data
@@ -115,9 +943,194 @@ mod tests {
             "4     3262.95527859238       -22.2275390625\n"
         );
 
-        let result = io::get_from_ascii(input_text);
+        let result = io::Spectrum::from_ascii(input_text).unwrap().get_int();
 
         // Test against a Vec<f64>
         assert_eq!(result, vec![4600.7724609375, 5483.7724609375, 1550.7724609375, -22.2275390625])
     }
+
+    #[test]
+    fn bad_token_in_spectrum() {
+        let input_text = concat!(
+            "index            Field [G]         Intensity []\n",
+            "1              3262.75      4600.7724609375\n",
+            "2     3262.81842619746      5483.77x4609375\n",
+        );
+
+        let err = io::Spectrum::from_ascii(input_text).err().unwrap();
+        assert_eq!(err, io::EsrafelIoError::Parse {
+            line: 3,
+            column: 3,
+            token: "5483.77x4609375".into(),
+            expected: "an intensity value",
+        });
+    }
+
+    #[test]
+    fn load_bes3t() {
+        let dsc = concat!(
+            "#DESC	1.2 * DESCRIPTOR INFORMATION ***********************\n",
+            "BSEQ	LIT\n",
+            "IKKF	REAL\n",
+            "XTYP	IDX\n",
+            "YTYP	NODATA\n",
+            "IRFMT	D\n",
+            "XPTS	3\n",
+            "XMIN	330.0\n",
+            "XWID	2.0\n",
+            "TITL	'probe'\n",
+            "XUNI	'mT'\n",
+            "#SPL	1.2 * STANDARD PARAMETER LAYER\n",
+            "MWFQ	9.5e+09\n",
+            "#DSL	1.0 * DEVICE SPECIFIC LAYER\n",
+            ".DVC     acqStart, 1.0\n",
+            "ModAmp             1.000 G\n",
+        );
+        let dta: Vec<u8> = [1.0_f64, -2.0, 0.5].iter().flat_map(|v| v.to_le_bytes()).collect();
+
+        let spectrum = io::Spectrum::from_bes3t(dsc, &dta).unwrap();
+        assert_eq!(spectrum.get_int(), vec![1.0, -2.0, 0.5]);
+        assert_eq!(spectrum.get_fld(), vec![3300.0, 3310.0, 3320.0]);
+        assert_eq!(spectrum.get_meta().mwfq, Some(9.5));
+        assert_eq!(spectrum.get_meta().mod_amp, Some(1.0));
+        assert_eq!(spectrum.get_meta().title.as_deref(), Some("probe"));
+
+        // Too few bytes for the announced points
+        let err = io::Spectrum::from_bes3t(dsc, &dta[..16]).err().unwrap();
+        assert_eq!(err, io::EsrafelIoError::BinarySize { expected: 24, found: 16 });
+
+        // Latin-1 comment, as Xepr writes it: not valid UTF-8
+        let mut latin1 = dsc.as_bytes().to_vec();
+        latin1.extend_from_slice(b"* Temperatur 20 \xb0C\n");
+        let decoded = io::decode_text(&latin1);
+        assert!(decoded.ends_with("20 °C\n"));
+        let spectrum = io::Spectrum::from_bes3t(&decoded, &dta).unwrap();
+        assert_eq!(spectrum.get_int(), vec![1.0, -2.0, 0.5]);
+    }
+
+    #[test]
+    fn load_winepr() {
+        let par = concat!(
+            "DOS  Format\n",
+            "JCO  probe\n",
+            "JUN  G\n",
+            "RES  4\n",
+            "HCF  3480.000000\n",
+            "HSW  60.000000\n",
+            "MF  9.780000\n",
+            "RMA  0.500000\n",
+        );
+        let spc: Vec<u8> = [0.0_f32, 1.0, -1.0, 0.0].iter().flat_map(|v| v.to_le_bytes()).collect();
+
+        let spectrum = io::Spectrum::from_winepr(par, &spc).unwrap();
+        assert_eq!(spectrum.get_int(), vec![0.0, 1.0, -1.0, 0.0]);
+        assert_eq!(spectrum.get_fld(), vec![3450.0, 3470.0, 3490.0, 3510.0]);
+        assert_eq!(spectrum.get_meta().mwfq, Some(9.78));
+        assert_eq!(spectrum.get_meta().mod_amp, Some(0.5));
+
+        // WinEPR writes the comment in the DOS/Windows code page
+        let mut latin1 = b"JCO  \xb5-probe\n".to_vec();
+        latin1.extend_from_slice(par.as_bytes());
+        let spectrum = io::Spectrum::from_winepr(&io::decode_text(&latin1), &spc).unwrap();
+        assert_eq!(spectrum.get_fld(), vec![3450.0, 3470.0, 3490.0, 3510.0]);
+    }
+
+    #[test]
+    fn jcampdx_round_trip() {
+        let fld: Vec<f64> = (0..50).map(|i| 3400.0 + 0.5 * i as f64).collect();
+        let int: Vec<f64> = (0..50).map(|i| if i < 20 { 3.0 } else { (i as f64 * 0.7).sin() * 1e4 }).collect();
+        let spectrum = io::Spectrum::new(fld.clone(), int.clone());
+
+        let loaded = io::Spectrum::from_jcampdx(&spectrum.into_jcampdx()).unwrap();
+        assert_eq!(loaded.get_fld(), fld);
+        for (a, b) in loaded.get_int().iter().zip(int.iter()) {
+            assert!((a - b).abs() < 1e-4);
+        }
+
+        // A single point, with its acquisition parameters
+        let mut spectrum = io::Spectrum::new(vec![3400.0], vec![-2.5]);
+        let meta = io::Acquisition { mwfq: Some(9.5), mod_amp: Some(0.5), ..Default::default() };
+        spectrum.set_meta(meta.clone());
+        let loaded = io::Spectrum::from_jcampdx(&spectrum.into_jcampdx()).unwrap();
+        assert_eq!(loaded.get_fld(), vec![3400.0]);
+        assert_eq!(loaded.get_int(), vec![-2.5]);
+        assert_eq!(loaded.get_meta().mwfq, meta.mwfq);
+        assert_eq!(loaded.get_meta().mod_amp, meta.mod_amp);
+    }
+
+    #[test]
+    fn load_jcampdx() {
+        // Hand-compressed DIFDUP table, with Y-checks at line starts
+        let content = concat!(
+            "##TITLE=probe\n",
+            "##JCAMP-DX=5.01\n",
+            "##XUNITS=mT\n",
+            "##$MWFQ=9.5e9\n",
+            "##FIRSTX=330\n",
+            "##LASTX=330.9\n",
+            "##NPOINTS=10\n",
+            "##XFACTOR=1\n",
+            "##YFACTOR=2\n",
+            "##XYDATA=(X++(Y..Y))\n",
+            "330A0J%T\n",
+            "330.3A1KkUml\n",
+            "330.9@\n",
+            "##END=\n",
+        );
+
+        let spectrum = io::Spectrum::from_jcampdx(content).unwrap();
+        assert_eq!(spectrum.get_int(), vec![20.0, 22.0, 22.0, 22.0, 26.0, 22.0, 18.0, 14.0, 6.0, 0.0]);
+        assert!((spectrum.get_fld()[9] - 3309.0).abs() < 1e-9);
+        assert_eq!(spectrum.get_meta().mwfq, Some(9.5));
+
+        // Field in tesla
+        let content = "##XUNITS=T\n##XYPOINTS=(XY..XY)\n0.33, 1.0; 0.331, 2.0\n##END=\n";
+        let spectrum = io::Spectrum::from_jcampdx(content).unwrap();
+        assert_eq!(spectrum.get_fld(), vec![3300.0, 3310.0]);
+
+        // A field value left without its intensity
+        let content = "##XYPOINTS=(XY..XY)\n3300, 1.0; 3301, 2.0\n3302, 3.0; 3303\n##END=\n";
+        assert!(matches!(
+            io::Spectrum::from_jcampdx(content),
+            Err(io::EsrafelIoError::Parse { line: 3, column: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn load_delimited_text() {
+        let input_text = concat!(
+            "Field [mT];Intensity\n",
+            "# exported by someone\n",
+            "326,275;4600,5\n",
+            "326,282;oops\n",
+            "326,289\n",
+            "326,296;-22,25\n",
+        );
+        let opts = io::TextOptions {
+            delimiter: Some(';'),
+            header_rows: 1,
+            decimal_comma: true,
+            ..io::TextOptions::default()
+        };
+
+        let (spectrum, report) = io::Spectrum::from_text(input_text, &opts).unwrap();
+        assert_eq!(spectrum.get_fld(), vec![326.275, 326.296]);
+        assert_eq!(spectrum.get_int(), vec![4600.5, -22.25]);
+        assert_eq!(report.rows, 2);
+        let reasons: Vec<io::SkipReason> = report.skipped.into_iter().map(|s| s.reason).collect();
+        assert_eq!(reasons, vec![
+            io::SkipReason::Header,
+            io::SkipReason::Comment,
+            io::SkipReason::NotNumeric { column: 2, token: "oops".into() },
+            io::SkipReason::Columns { found: 1, needed: 2 },
+        ]);
+    }
+
+    #[test]
+    fn truncated_simfile() {
+        let input_text = " 1\n 1024\n 70\n 100\n .11\n";
+
+        let err = io::SimulationState::from_simfile(input_text).err().unwrap();
+        assert_eq!(err, io::EsrafelIoError::UnexpectedEof { line: 6, expected: "the line width" });
+    }
 }
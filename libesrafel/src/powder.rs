@@ -0,0 +1,268 @@
+// Powder and frozen solution spectra: rhombic g and hyperfine tensors,
+// integrated over the orientations of a spherical grid.
+// Hyperfine lines are first order, the lineshape is the same Lorentzian/Gaussian
+// mix of the isotropic simulation.
+use crate::{Param, Radical};
+use crate::eprft::{binned, interpolate, mc_fit_with, modulated, uniform_step, OutputMode, Shape, SimOptions, G_MIN, H_OVER_MUB};
+use crate::hyperfine::multiplet;
+use rand::rngs::StdRng;
+use serde::{Serialize, Deserialize};
+
+type Matrix = [[f64; 3]; 3];
+
+// Principal values and orientation of a tensor
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Tensor {
+    pub principal: [Param; 3],  // x, y, z
+    #[serde(default)]
+    pub euler: [f64; 3],  // zyz Euler angles from the molecular frame [deg]
+}
+
+impl Tensor {
+    pub fn rhombic(x: f64, y: f64, z: f64) -> Self {
+        Tensor {
+            principal: [Param::set(x, 0.0), Param::set(y, 0.0), Param::set(z, 0.0)],
+            euler: [0.0; 3],
+        }
+    }
+
+    pub fn axial(perpendicular: f64, parallel: f64) -> Self {
+        Tensor::rhombic(perpendicular, perpendicular, parallel)
+    }
+
+    pub fn isotropic(val: f64) -> Self {
+        Tensor::rhombic(val, val, val)
+    }
+
+    pub fn iso(&self) -> f64 {
+        self.principal.iter().map(|p| p.val).sum::<f64>() / 3.0
+    }
+
+    fn randomize(&self, rng: &mut StdRng) -> Self {
+        Tensor {
+            principal: [
+                self.principal[0].randomize(rng),
+                self.principal[1].randomize(rng),
+                self.principal[2].randomize(rng),
+            ],
+            euler: self.euler,
+        }
+    }
+
+    // R diag(principal) Rᵀ, with R = Rz(α) Ry(β) Rz(γ)
+    fn matrix(&self) -> Matrix {
+        let [a, b, c] = self.euler.map(f64::to_radians);
+        let rz = |t: f64| [[t.cos(), -t.sin(), 0.0], [t.sin(), t.cos(), 0.0], [0.0, 0.0, 1.0]];
+        let ry = [[b.cos(), 0.0, b.sin()], [0.0, 1.0, 0.0], [-b.sin(), 0.0, b.cos()]];
+        let r = mul(&mul(&rz(a), &ry), &rz(c));
+
+        let mut m = [[0.0; 3]; 3];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, cell) in row.iter_mut().enumerate() {
+                *cell = (0..3).map(|k| r[i][k] * self.principal[k].val * r[j][k]).sum();
+            }
+        }
+        m
+    }
+}
+
+fn mul(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut m = [[0.0; 3]; 3];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, cell) in row.iter_mut().enumerate() {
+            *cell = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    m
+}
+
+fn apply(m: &Matrix, v: &[f64; 3]) -> [f64; 3] {
+    [0, 1, 2].map(|i| m[i][0] * v[0] + m[i][1] * v[1] + m[i][2] * v[2])
+}
+
+fn norm(v: &[f64; 3]) -> f64 {
+    (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt()
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PowderNucleus {
+    pub spin: Param,
+    pub eqs: Param,
+    pub a: Tensor,  // Hyperfine tensor [G]
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PowderRadical {
+    pub lwa: Param,  // Line width
+    pub lrtz: Param,  // Lorentzian linewidth parameter (%)
+    pub amount: Param,  // Relative amount
+    #[serde(default)]
+    pub dh1: Param,  // Field offset [G], on top of g
+    #[serde(default)]
+    pub phase: Param,  // Detection phase [deg]
+    pub g: Tensor,  // Its frame is the molecular frame, usually with no Euler angles
+    pub nucs: Vec<PowderNucleus>,
+}
+
+// Isotropic tensors from an isotropic radical; a starting point to add anisotropy
+impl From<&Radical> for PowderRadical {
+    fn from(rad: &Radical) -> Self {
+        let iso = |p: &Param| Tensor {
+            principal: [p.clone(), p.clone(), p.clone()],
+            euler: [0.0; 3],
+        };
+
+        PowderRadical {
+            lwa: rad.lwa.clone(),
+            lrtz: rad.lrtz.clone(),
+            amount: rad.amount.clone(),
+            dh1: rad.dh1.clone(),
+            phase: rad.phase.clone(),
+            g: iso(&rad.g),
+            nucs: rad.nucs.iter().map(|nuc| PowderNucleus {
+                spin: nuc.spin.clone(),
+                eqs: nuc.eqs.clone(),
+                a: iso(&nuc.hpf),
+            }).collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PowderOptions {
+    pub knots: usize,  // Orientations between the pole and the equator
+}
+
+impl Default for PowderOptions {
+    fn default() -> Self {
+        PowderOptions { knots: 30 }
+    }
+}
+
+// Unit vectors on the upper hemisphere (the lower one gives the same spectrum),
+// in rings of constant θ with about the same spacing along φ; weights sum up to 1
+fn orientations(knots: usize) -> Vec<([f64; 3], f64)> {
+    let knots = knots.max(1);
+    let dtheta = std::f64::consts::FRAC_PI_2 / knots as f64;
+    let mut grid = Vec::new();
+
+    for i in 0..knots {
+        let theta = (i as f64 + 0.5) * dtheta;
+        let ring = ((4.0 * knots as f64 * theta.sin()).round() as usize).max(1);
+        let weight = theta.sin() / ring as f64;
+
+        for j in 0..ring {
+            let phi = 2.0 * std::f64::consts::PI * j as f64 / ring as f64;
+            let n = [theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos()];
+            grid.push((n, weight));
+        }
+    }
+
+    let total: f64 = grid.iter().map(|(_, w)| w).sum();
+    grid.into_iter().map(|(n, w)| (n, w / total)).collect()
+}
+
+// Calculate the powder spectrum on a field axis [G].
+// Without the microwave frequency, every radical has its isotropic g at the center of the axis.
+// The lines of every orientation are binned on a uniform grid, like `eprft::calcola_fld` does;
+// any other axis is simulated on a uniform grid over the same range, then interpolated.
+pub fn calcola(rads: &[PowderRadical], fld: &[f64], sim: &SimOptions, powder: &PowderOptions) -> Vec<f64> {
+    let points = fld.len();
+    if points < 2 {
+        return vec![0.0; points];
+    }
+
+    if let Some(modulation) = sim.modulation.as_ref().filter(|m| m.amplitude > 0.0) {
+        let absorption = SimOptions { output: OutputMode::Absorption, modulation: None, ..sim.clone() };
+        return modulated(fld, modulation, |grid| calcola(rads, grid, &absorption, powder));
+    }
+
+    let lo = fld.iter().cloned().fold(f64::INFINITY, f64::min);
+    let hi = fld.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let step = (hi - lo) / (points - 1) as f64;
+    let grid = orientations(powder.knots);
+    let mut newteor = vec![0.0; points];
+
+    for rad in rads {
+        // Like `eprft::calcola_fld`, a line with no width is not drawn
+        if rad.lwa.val <= 0.0 {
+            continue;
+        }
+        let mwfq = sim.mwfq.unwrap_or((lo + hi) / 2.0 * rad.g.iso() / H_OVER_MUB);
+        let g = rad.g.matrix();
+        let hpf: Vec<Matrix> = rad.nucs.iter().map(|nuc| nuc.a.matrix()).collect();
+        let counts: Vec<Vec<f64>> = rad.nucs.iter().map(|nuc| {
+            let two_i = (2.0 * nuc.spin.val).round().max(0.0) as usize;
+            multiplet(two_i, nuc.eqs.val.round().max(0.0) as usize)
+        }).collect();
+
+        // (field, intensity) of every line of every orientation
+        let mut sticks = Vec::new();
+        for (n, weight) in &grid {
+            let gn = apply(&g, n);
+            let g_eff = norm(&gn);
+            let mut lines = vec![(H_OVER_MUB * mwfq / g_eff + rad.dh1.val, *weight)];
+
+            for (a, c) in hpf.iter().zip(counts.iter()) {
+                let a_eff = norm(&apply(a, &gn)) / g_eff;
+                let half = (c.len() - 1) as f64 / 2.0;
+                lines = lines.iter().flat_map(|(b, int)| {
+                    c.iter().enumerate().map(move |(k, count)| (b - a_eff * (k as f64 - half), int * count))
+                }).collect();
+            }
+            sticks.extend(lines);
+        }
+
+        let totale: f64 = sticks.iter().map(|(_, int)| int).sum();
+        let shape = Shape::new(rad.lwa.val, rad.lrtz.val, rad.amount.val, rad.lwa.val, totale).phased(rad.phase.val);
+        let spectrum = binned(&sticks, &shape, rad.lwa.val, lo, step, points, sim.output);
+        for (y, v) in newteor.iter_mut().zip(spectrum) {
+            *y += v;
+        }
+    }
+
+    if uniform_step(fld).is_some() {
+        return newteor;
+    }
+    fld.iter().map(|x| interpolate(&newteor, lo, step, *x)).collect()
+}
+
+// Reset potentially aberrant values, like `eprft` does
+fn check_pars(mut rad: PowderRadical) -> PowderRadical {
+    if rad.lwa.val < 0.0 { rad.lwa.val = 0.0 };
+    if rad.lrtz.val < 0.0 { rad.lrtz.val = 0.0 };
+    if rad.amount.val < 0.0 { rad.amount.val = 0.0 };
+    if rad.lrtz.val > 100.0 { rad.lrtz.val = 100.0 };
+    for g in rad.g.principal.iter_mut() {
+        if g.val < G_MIN { g.val = G_MIN };
+    }
+    rad
+}
+
+fn caso(rads: &[PowderRadical], rng: &mut StdRng) -> Vec<PowderRadical> {
+    rads.iter().cloned().map(|mut rad| {
+        rad.lwa = rad.lwa.randomize(rng);
+        rad.lrtz = rad.lrtz.randomize(rng);
+        rad.amount = rad.amount.randomize(rng);
+        rad.dh1 = rad.dh1.randomize(rng);
+        rad.phase = rad.phase.randomize(rng);
+        rad.g = rad.g.randomize(rng);
+        for nuc in rad.nucs.iter_mut() {
+            nuc.a = nuc.a.randomize(rng);
+        }
+        check_pars(rad)
+    }).collect()
+}
+
+// One Monte Carlo iteration, as `eprft::mc_fit`
+pub fn mc_fit(
+    empirical: &[f64],
+    fld: &[f64],
+    sim: &SimOptions,
+    powder: &PowderOptions,
+    sigma: f64,
+    rads: Vec<PowderRadical>,
+    rng: &mut StdRng) -> (f64, Vec<f64>, Vec<PowderRadical>) {
+
+    mc_fit_with(empirical, sigma, sim.baseline, rads, rng, caso, |newrads| calcola(newrads, fld, sim, powder))
+}
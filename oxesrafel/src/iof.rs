@@ -1,27 +1,118 @@
 use pyo3::prelude::*;
+use pyo3::exceptions::PyValueError;
+use pyo3::types::PyDict;
 use crate::sim::rad_to_py;
 use crate::rad::Radical;
-use libesrafel::io::SimulationState;
-use libesrafel::io::Spectrum;
+use libesrafel::io::{Acquisition, SimulationState, Spectrum, EsrafelIoError, TextOptions};
+
+// (indexes, field, intensity, skipped rows as (line, reason))
+type TextTuple = (Vec<usize>, Vec<f64>, Vec<f64>, Vec<(usize, String)>);
+// (indexes, field, intensity, acquisition parameters)
+type JcampTuple = (Vec<usize>, Vec<f64>, Vec<f64>, Py<PyDict>);
+
+fn io_err(e: EsrafelIoError) -> PyErr {
+    PyValueError::new_err(e.to_string())
+}
+
+fn json_err(e: impl std::fmt::Display) -> PyErr {
+    PyValueError::new_err(e.to_string())
+}
 
 #[pyfunction]
 pub fn ascii_import(content: &str) -> PyResult<(Vec<usize>, Vec<f64>, Vec<f64>)> {
-    Ok(Spectrum::from_ascii(content).into_tuple())
+    Ok(Spectrum::from_ascii(content).map_err(io_err)?.into_tuple())
 }
 
 #[pyfunction]
 pub fn ascii_to_json(content: &str) -> PyResult<String> {
-    Ok(Spectrum::from_ascii(content).into_json().unwrap())
+    Spectrum::from_ascii(content).map_err(io_err)?.into_json().map_err(json_err)
+}
+
+// Delimited text (CSV, TSV, ...); also returns the skipped rows as (line, reason)
+#[pyfunction]
+#[pyo3(signature = (content, delimiter=None, comment="#".to_string(), header_rows=0, field_col=0, intensity_col=1, decimal_comma=false))]
+pub fn text_import(
+    content: &str,
+    delimiter: Option<char>,
+    comment: Option<String>,
+    header_rows: usize,
+    field_col: Option<usize>,
+    intensity_col: usize,
+    decimal_comma: bool) -> PyResult<TextTuple> {
+
+    let opts = TextOptions { delimiter, comment, header_rows, field_col, intensity_col, decimal_comma };
+    let (spectrum, report) = Spectrum::from_text(content, &opts).map_err(io_err)?;
+    let (idx, fld, int) = spectrum.into_tuple();
+    let skipped = report.skipped.into_iter().map(|s| (s.line, s.reason.to_string())).collect();
+    Ok((idx, fld, int, skipped))
 }
 
 #[pyfunction]
 pub fn get_from_sim(content: &str) -> PyResult<(i32, i32, Vec<Radical>)> {
-    let (points, sweep, rads) = SimulationState::from_simfile(content).into_tuple();
+    let (points, sweep, rads) = SimulationState::from_simfile(content).map_err(io_err)?.into_tuple();
     let rads = rads.into_iter().map(|r| rad_to_py(&r)).collect();
     Ok((points, sweep, rads))
 }
 
 #[pyfunction]
 pub fn sim_as_json(content: &str) -> PyResult<String> {
-    Ok(SimulationState::from_simfile(content).into_json().unwrap())
+    SimulationState::from_simfile(content).map_err(io_err)?.into_json().map_err(json_err)
+}
+
+// Acquisition parameters as a dict: title, mwfq [GHz], power [mW],
+// mod_amp [G], mod_freq [kHz], gain [dB], temperature [K].
+// Parameters missing from the file are missing from the dict too.
+fn meta_to_dict(py: Python, meta: &Acquisition) -> PyResult<Py<PyDict>> {
+    let dict = PyDict::new(py);
+    if let Some(title) = &meta.title { dict.set_item("title", title)?; }
+    let numbers = [
+        ("mwfq", meta.mwfq),
+        ("power", meta.power),
+        ("mod_amp", meta.mod_amp),
+        ("mod_freq", meta.mod_freq),
+        ("gain", meta.gain),
+        ("temperature", meta.temperature),
+    ];
+    for (key, value) in numbers {
+        if let Some(v) = value { dict.set_item(key, v)?; }
+    }
+    Ok(dict.into())
+}
+
+fn dict_to_meta(dict: &PyDict) -> PyResult<Acquisition> {
+    let mut meta = Acquisition::default();
+    for (key, value) in dict.iter() {
+        let key: String = key.extract()?;
+        match key.as_str() {
+            "title" => meta.title = value.extract()?,
+            "mwfq" => meta.mwfq = value.extract()?,
+            "power" => meta.power = value.extract()?,
+            "mod_amp" => meta.mod_amp = value.extract()?,
+            "mod_freq" => meta.mod_freq = value.extract()?,
+            "gain" => meta.gain = value.extract()?,
+            "temperature" => meta.temperature = value.extract()?,
+            _ => return Err(PyValueError::new_err(format!("unknown acquisition parameter {:?}", key))),
+        }
+    }
+    Ok(meta)
+}
+
+#[pyfunction]
+pub fn jcampdx_import(py: Python, content: &str) -> PyResult<JcampTuple> {
+    let spectrum = Spectrum::from_jcampdx(content).map_err(io_err)?;
+    let (idx, fld, int) = spectrum.into_tuple();
+    Ok((idx, fld, int, meta_to_dict(py, spectrum.get_meta())?))
+}
+
+#[pyfunction]
+#[pyo3(signature = (fld, int, meta=None))]
+pub fn jcampdx_export(fld: Vec<f64>, int: Vec<f64>, meta: Option<&PyDict>) -> PyResult<String> {
+    if fld.len() != int.len() {
+        return Err(PyValueError::new_err("field and intensity must have the same length"));
+    }
+    let mut spectrum = Spectrum::new(fld, int);
+    if let Some(meta) = meta {
+        spectrum.set_meta(dict_to_meta(meta)?);
+    }
+    Ok(spectrum.into_jcampdx())
 }
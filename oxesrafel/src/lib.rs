@@ -3,16 +3,23 @@ mod nuc;
 mod rad;
 mod sim;
 mod iof;
+mod rng;
 
 use pyo3::prelude::*;
 use crate::par::Param;
 use crate::nuc::Nucleus;
 use crate::rad::Radical;
 use crate::sim::Simulator;
+use crate::rng::Rng;
+use crate::sim::stick_spectrum;
+use crate::sim::fit_baseline;
 use crate::iof::ascii_import;
 use crate::iof::ascii_to_json;
+use crate::iof::text_import;
 use crate::iof::get_from_sim;
 use crate::iof::sim_as_json;
+use crate::iof::jcampdx_import;
+use crate::iof::jcampdx_export;
 
 /// Formats the sum of two numbers as string.
 #[pyfunction]
@@ -26,11 +33,17 @@ fn oxesrafel(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(sum_as_string, m)?)?;
     m.add_function(wrap_pyfunction!(ascii_import, m)?)?;
     m.add_function(wrap_pyfunction!(ascii_to_json, m)?)?;
+    m.add_function(wrap_pyfunction!(text_import, m)?)?;
     m.add_function(wrap_pyfunction!(get_from_sim, m)?)?;
     m.add_function(wrap_pyfunction!(sim_as_json, m)?)?;
+    m.add_function(wrap_pyfunction!(jcampdx_import, m)?)?;
+    m.add_function(wrap_pyfunction!(jcampdx_export, m)?)?;
+    m.add_function(wrap_pyfunction!(stick_spectrum, m)?)?;
+    m.add_function(wrap_pyfunction!(fit_baseline, m)?)?;
     m.add_class::<Param>()?;
     m.add_class::<Nucleus>()?;
     m.add_class::<Radical>()?;
     m.add_class::<Simulator>()?;
+    m.add_class::<Rng>()?;
     Ok(())
 }
@@ -1,4 +1,7 @@
 use pyo3::prelude::*;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use crate::rng::Rng;
 
 #[derive(Clone, Debug, Copy)]
 #[pyclass]
@@ -36,8 +39,14 @@ impl Param {
         Ok(())
     }
 
-    pub fn randomize(&mut self) -> PyResult<()> {
-        let temp = libesrafel::Param::set(self.val, self.var).randomize();
+    // Draws from `rng` when given, so a seeded run can be replayed
+    #[pyo3(signature = (rng=None))]
+    pub fn randomize(&mut self, rng: Option<PyRefMut<Rng>>) -> PyResult<()> {
+        let param = libesrafel::Param::set(self.val, self.var);
+        let temp = match rng {
+            Some(mut rng) => param.randomize(&mut rng.rng),
+            None => param.randomize(&mut StdRng::from_entropy()),
+        };
         self.val = temp.val;
         Ok(())
     }
@@ -1,4 +1,6 @@
 use pyo3::prelude::*;
+use pyo3::exceptions::PyValueError;
+use libesrafel::Lineshape;
 use crate::par::Param;
 use crate::nuc::Nucleus;
 
@@ -6,26 +8,73 @@ use crate::nuc::Nucleus;
 #[pyclass]
 pub struct Radical {
     pub lwa: Param,
+    pub lwb: Param,  // Width of each line is lwa + lwb·M + lwc·M²
+    pub lwc: Param,
     pub lrtz: Param,
+    pub lwl: Param,  // Lorentzian width of the Voigt lineshape
+    pub lineshape: Lineshape,
+    pub phase: Param,  // Detection phase [deg]
     pub amount: Param,
     pub dh1: Param,
+    pub g: Param,
     pub nucs: Vec<Nucleus>,
 }
 
+fn lineshape_from_str(name: &str) -> PyResult<Lineshape> {
+    match name {
+        "pseudo-voigt" => Ok(Lineshape::PseudoVoigt),
+        "voigt" => Ok(Lineshape::Voigt),
+        _ => Err(PyValueError::new_err(format!("unknown lineshape {:?}; use pseudo-voigt or voigt", name))),
+    }
+}
+
+fn lineshape_to_str(lineshape: Lineshape) -> &'static str {
+    match lineshape {
+        Lineshape::PseudoVoigt => "pseudo-voigt",
+        Lineshape::Voigt => "voigt",
+    }
+}
+
 #[pymethods]
 impl Radical {
     #[new]
-    pub fn new(lwa: Param, lrtz: Param, amount: Param, dh1: Param, nucs: Vec<Nucleus>) -> Self {
-        Self { lwa, lrtz, amount, dh1, nucs }
+    #[pyo3(signature = (lwa, lrtz, amount, dh1, nucs, g=None, lwb=None, lwc=None, lwl=None, lineshape="pseudo-voigt", phase=None))]
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        lwa: Param,
+        lrtz: Param,
+        amount: Param,
+        dh1: Param,
+        nucs: Vec<Nucleus>,
+        g: Option<Param>,
+        lwb: Option<Param>,
+        lwc: Option<Param>,
+        lwl: Option<Param>,
+        lineshape: &str,
+        phase: Option<Param>,
+    ) -> PyResult<Self> {
+        let g = g.unwrap_or(Param::new(libesrafel::G_E, 0.0));
+        let lwb = lwb.unwrap_or(Param::new(0.0, 0.0));
+        let lwc = lwc.unwrap_or(Param::new(0.0, 0.0));
+        let lwl = lwl.unwrap_or(Param::new(0.0, 0.0));
+        let lineshape = lineshape_from_str(lineshape)?;
+        let phase = phase.unwrap_or(Param::new(0.0, 0.0));
+        Ok(Self { lwa, lwb, lwc, lrtz, lwl, lineshape, phase, amount, dh1, g, nucs })
     }
 
     #[staticmethod]
     pub fn probe() -> Self {
         Self {
             lwa: Param::new(1.0, 0.0),
+            lwb: Param::new(0.0, 0.0),
+            lwc: Param::new(0.0, 0.0),
             lrtz: Param::new(50.0, 0.0),
+            lwl: Param::new(0.0, 0.0),
+            lineshape: Lineshape::PseudoVoigt,
+            phase: Param::new(0.0, 0.0),
             amount: Param::new(100.0, 0.0),
             dh1: Param::new(0.0, 0.0),
+            g: Param::new(libesrafel::G_E, 0.0),
             nucs: Vec::new(),
         }
     }
@@ -41,6 +90,28 @@ impl Radical {
         Ok(())
     }
 
+    #[getter]
+    pub fn get_lwb(&self) -> PyResult<Param> {
+        Ok(self.lwb)
+    }
+
+    #[setter]
+    pub fn set_lwb(&mut self, value: Param) -> PyResult<()> {
+        self.lwb = value;
+        Ok(())
+    }
+
+    #[getter]
+    pub fn get_lwc(&self) -> PyResult<Param> {
+        Ok(self.lwc)
+    }
+
+    #[setter]
+    pub fn set_lwc(&mut self, value: Param) -> PyResult<()> {
+        self.lwc = value;
+        Ok(())
+    }
+
     #[getter]
     pub fn get_lrtz(&self) -> PyResult<Param> {
         Ok(self.lrtz)
@@ -52,6 +123,39 @@ impl Radical {
         Ok(())
     }
 
+    #[getter]
+    pub fn get_lwl(&self) -> PyResult<Param> {
+        Ok(self.lwl)
+    }
+
+    #[setter]
+    pub fn set_lwl(&mut self, value: Param) -> PyResult<()> {
+        self.lwl = value;
+        Ok(())
+    }
+
+    #[getter]
+    pub fn get_lineshape(&self) -> PyResult<&'static str> {
+        Ok(lineshape_to_str(self.lineshape))
+    }
+
+    #[setter]
+    pub fn set_lineshape(&mut self, value: &str) -> PyResult<()> {
+        self.lineshape = lineshape_from_str(value)?;
+        Ok(())
+    }
+
+    #[getter]
+    pub fn get_phase(&self) -> PyResult<Param> {
+        Ok(self.phase)
+    }
+
+    #[setter]
+    pub fn set_phase(&mut self, value: Param) -> PyResult<()> {
+        self.phase = value;
+        Ok(())
+    }
+
     #[getter]
     pub fn get_amount(&self) -> PyResult<Param> {
         Ok(self.amount)
@@ -74,6 +178,17 @@ impl Radical {
         Ok(())
     }
 
+    #[getter]
+    pub fn get_g(&self) -> PyResult<Param> {
+        Ok(self.g)
+    }
+
+    #[setter]
+    pub fn set_g(&mut self, value: Param) -> PyResult<()> {
+        self.g = value;
+        Ok(())
+    }
+
     #[getter]
     pub fn get_nucs(&self) -> PyResult<Vec<Nucleus>> {
         Ok(self.nucs.clone())
@@ -0,0 +1,27 @@
+use pyo3::prelude::*;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+// Random numbers for `Param.randomize`: one generator for a whole run,
+// so the same seed replays it and every parameter gets its own draw
+#[pyclass]
+pub struct Rng {
+    pub rng: StdRng,
+    pub seed: u64,
+}
+
+#[pymethods]
+impl Rng {
+    // Without a seed one is drawn; read it back from `seed`
+    #[new]
+    #[pyo3(signature = (seed=None))]
+    pub fn new(seed: Option<u64>) -> Self {
+        let seed = seed.unwrap_or_else(rand::random);
+        Rng { rng: StdRng::seed_from_u64(seed), seed }
+    }
+
+    #[getter]
+    pub fn get_seed(&self) -> PyResult<u64> {
+        Ok(self.seed)
+    }
+}
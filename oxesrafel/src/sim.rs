@@ -1,13 +1,59 @@
 use pyo3::prelude::*;
+use pyo3::exceptions::PyValueError;
+use libesrafel::LinePositions;
+use libesrafel::backend::{Model, Optimizer, SimulationMethod, SpectrumModel};
+use libesrafel::eprft::{Annealing, Baseline, Modulation, OutputMode};
+use libesrafel::fit::{LevMar, Simplex};
 use crate::nuc::Nucleus;
 use crate::rad::Radical;
 use crate::par::Param;
 
+// (names, values, errors, correlation matrix, strongly correlated pairs)
+type UncertaintyTuple = (Vec<String>, Vec<f64>, Vec<f64>, Vec<Vec<f64>>, Vec<(String, String, f64)>);
+
 #[pyclass]
 pub struct Simulator {
     pub rads: Vec<Radical>,
     pub sweep: f64,
     pub points: f64,
+    pub opts: libesrafel::eprft::SimOptions,
+    pub seed: Option<u64>,  // Of the last random run
+}
+
+fn positions_from_str(name: &str) -> PyResult<LinePositions> {
+    match name {
+        "first" => Ok(LinePositions::FirstOrder),
+        "second" => Ok(LinePositions::SecondOrder),
+        "breit-rabi" => Ok(LinePositions::BreitRabi),
+        _ => Err(PyValueError::new_err(format!("unknown line positions {:?}; use first, second or breit-rabi", name))),
+    }
+}
+
+fn positions_to_str(positions: LinePositions) -> &'static str {
+    match positions {
+        LinePositions::FirstOrder => "first",
+        LinePositions::SecondOrder => "second",
+        LinePositions::BreitRabi => "breit-rabi",
+    }
+}
+
+fn output_from_str(name: &str) -> PyResult<OutputMode> {
+    match name {
+        "absorption" => Ok(OutputMode::Absorption),
+        "first-derivative" => Ok(OutputMode::FirstDerivative),
+        "second-derivative" => Ok(OutputMode::SecondDerivative),
+        _ => Err(PyValueError::new_err(format!(
+            "unknown output {:?}; use absorption, first-derivative or second-derivative", name
+        ))),
+    }
+}
+
+fn output_to_str(output: OutputMode) -> &'static str {
+    match output {
+        OutputMode::Absorption => "absorption",
+        OutputMode::FirstDerivative => "first-derivative",
+        OutputMode::SecondDerivative => "second-derivative",
+    }
 }
 
 // TODO impl for nucs and rads (no py methods!)
@@ -32,9 +78,15 @@ fn rad_to_rs(rad: &Radical) -> libesrafel::Radical {
 
     libesrafel::Radical {
         lwa: libesrafel::Param::set(rad.lwa.val, rad.lwa.var),
+        lwb: libesrafel::Param::set(rad.lwb.val, rad.lwb.var),
+        lwc: libesrafel::Param::set(rad.lwc.val, rad.lwc.var),
         lrtz: libesrafel::Param::set(rad.lrtz.val, rad.lrtz.var),
+        lwl: libesrafel::Param::set(rad.lwl.val, rad.lwl.var),
+        lineshape: rad.lineshape,
+        phase: libesrafel::Param::set(rad.phase.val, rad.phase.var),
         amount: libesrafel::Param::set(rad.amount.val, rad.amount.var),
         dh1: libesrafel::Param::set(rad.dh1.val, rad.dh1.var),
+        g: libesrafel::Param::set(rad.g.val, rad.g.var),
         nucs,
     }
 }
@@ -44,23 +96,298 @@ pub fn rad_to_py(rad: &libesrafel::Radical) -> Radical {
 
     Radical {
         lwa: Param::new(rad.lwa.val, rad.lwa.var),
+        lwb: Param::new(rad.lwb.val, rad.lwb.var),
+        lwc: Param::new(rad.lwc.val, rad.lwc.var),
         lrtz: Param::new(rad.lrtz.val, rad.lrtz.var),
+        lwl: Param::new(rad.lwl.val, rad.lwl.var),
+        lineshape: rad.lineshape,
+        phase: Param::new(rad.phase.val, rad.phase.var),
         amount: Param::new(rad.amount.val, rad.amount.var),
         dh1: Param::new(rad.dh1.val, rad.dh1.var),
+        g: Param::new(rad.g.val, rad.g.var),
         nucs,
     }
 }
 
+// First order lines as (field offset, intensity, M_I of every nucleus group)
+#[pyfunction]
+pub fn stick_spectrum(rad: Radical) -> PyResult<Vec<(f64, f64, Vec<f64>)>> {
+    let lines = libesrafel::stick_spectrum(&rad_to_rs(&rad));
+    Ok(lines.into_iter().map(|l| (l.field, l.intensity, l.m_i)).collect())
+}
+
+// Best exp ≈ norma·teor + baseline, with a polynomial baseline of the given order:
+// (norma, Legendre coefficients over the points scaled to [-1, 1], baseline at every point)
+#[pyfunction]
+pub fn fit_baseline(exp: Vec<f64>, teor: Vec<f64>, order: usize) -> PyResult<(f64, Vec<f64>, Vec<f64>)> {
+    match libesrafel::eprft::fit_baseline(&exp, &teor, order) {
+        Some(fit) => Ok((fit.norma, fit.coeffs, fit.values)),
+        None => Err(PyValueError::new_err("cannot fit a baseline: too few points, or a flat spectrum")),
+    }
+}
+
+fn method_from_str(name: &str) -> PyResult<SimulationMethod> {
+    match name {
+        "mc" => Ok(SimulationMethod::MC199),
+        "annealing" => Ok(SimulationMethod::Annealing),
+        "nelder-mead" => Ok(SimulationMethod::NelderMead),
+        _ => Err(PyValueError::new_err(format!("unknown method {:?}; use mc, annealing or nelder-mead", name))),
+    }
+}
+
+// Shared by every fitting method
+impl Simulator {
+    fn model(&self) -> Model {
+        Model::Isotropic(self.rads.iter().map(rad_to_rs).collect())
+    }
+
+    // Step `optimizer` up to `iters` times, or until it converges; keeps the best radicals
+    fn run(&mut self, mut optimizer: Box<dyn Optimizer>, empirical: &[f64], fld: &[f64], iters: usize) -> (f64, Vec<f64>, Vec<Radical>) {
+        for _ in 0..iters {
+            if optimizer.converged() {
+                break;
+            }
+            optimizer.step(empirical, fld, &self.opts);
+        }
+
+        let best = optimizer.best();
+        let (sigma, teor) = libesrafel::eprft::errore_with(empirical, best.simulate(fld, &self.opts), self.opts.baseline);
+        if let Model::Isotropic(rads) = best {
+            self.rads = rads.iter().map(rad_to_py).collect();
+        }
+        (sigma, teor, self.rads.clone())
+    }
+
+    // The seed of a new run, kept to replay it
+    fn draw_seed(&mut self, seed: Option<u64>) -> u64 {
+        let seed = seed.unwrap_or_else(rand::random);
+        self.seed = Some(seed);
+        seed
+    }
+}
+
 #[pymethods]
 impl Simulator {
     #[new]
-    pub fn new(sweep: f64, points: f64, rads: Vec<Radical>) -> Self {
-        Self { sweep, points, rads }
+    #[pyo3(signature = (sweep, points, rads, mwfq=None, positions="first", output="first-derivative",
+                        modulation=None, harmonic=1))]
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        sweep: f64,
+        points: f64,
+        rads: Vec<Radical>,
+        mwfq: Option<f64>,
+        positions: &str,
+        output: &str,
+        modulation: Option<f64>,
+        harmonic: u32,
+    ) -> PyResult<Self> {
+        let opts = libesrafel::eprft::SimOptions {
+            mwfq,
+            positions: positions_from_str(positions)?,
+            output: output_from_str(output)?,
+            modulation: modulation.map(|amplitude| Modulation { amplitude, harmonic }),
+            baseline: Baseline::None,
+        };
+        Ok(Self { sweep, points, rads, opts, seed: None })
     }
 
+    // Uniform axis from 0 to sweep; same as the legacy `calcola` with default options
     pub fn calc(&self) -> PyResult<Vec<f64>> {
-        let rads = &self.rads.clone().into_iter().map(|r| rad_to_rs(&r)).collect();
-        Ok(libesrafel::eprft::calcola(rads, self.sweep, self.points))
+        let points = self.points as usize;
+        let step = self.sweep / (points.max(2) - 1) as f64;
+        let fld: Vec<f64> = (0..points).map(|i| i as f64 * step).collect();
+        self.calc_fld(fld)
+    }
+
+    // Simulate on an experimental field axis, ignoring sweep and points.
+    // With a microwave frequency, radicals sit at their g-value.
+    pub fn calc_fld(&self, fld: Vec<f64>) -> PyResult<Vec<f64>> {
+        let rads: Vec<libesrafel::Radical> = self.rads.iter().map(rad_to_rs).collect();
+        Ok(libesrafel::eprft::calcola_fld(&rads, &fld, &self.opts))
+    }
+
+    // Simulated annealing of the radicals against `empirical`, on its field axis `fld`;
+    // keeps the best radicals and returns (sigma, normalized spectrum, radicals).
+    // The same seed replays the same run; without one, it's drawn and kept in `seed`
+    #[pyo3(signature = (empirical, fld, iters, temperature=0.05, cooling=0.995, reheat_after=500, reheats=3, seed=None))]
+    #[allow(clippy::too_many_arguments)]
+    pub fn anneal(
+        &mut self,
+        empirical: Vec<f64>,
+        fld: Vec<f64>,
+        iters: usize,
+        temperature: f64,
+        cooling: f64,
+        reheat_after: usize,
+        reheats: usize,
+        seed: Option<u64>,
+    ) -> PyResult<(f64, Vec<f64>, Vec<Radical>)> {
+        if empirical.len() != fld.len() {
+            return Err(PyValueError::new_err("empirical and fld must have the same length"));
+        }
+
+        let schedule = Annealing { temperature, cooling, reheat_after, reheats };
+        let seed = self.draw_seed(seed);
+        let optimizer = SimulationMethod::Annealing.optimizer(
+            self.model(), f64::INFINITY, &schedule, seed, &empirical, &fld, &self.opts);
+        Ok(self.run(optimizer, &empirical, &fld, iters))
+    }
+
+    // Fit with any method: "mc" (the 1999 random walk), "annealing" or "nelder-mead", with their defaults;
+    // keeps the best radicals and returns (sigma, normalized spectrum, radicals).
+    // Random methods replay with `seed`; without one, it's drawn and kept in `seed`
+    #[pyo3(signature = (empirical, fld, iters, method="mc", seed=None))]
+    pub fn fit(
+        &mut self,
+        empirical: Vec<f64>,
+        fld: Vec<f64>,
+        iters: usize,
+        method: &str,
+        seed: Option<u64>,
+    ) -> PyResult<(f64, Vec<f64>, Vec<Radical>)> {
+        if empirical.len() != fld.len() {
+            return Err(PyValueError::new_err("empirical and fld must have the same length"));
+        }
+
+        let method = method_from_str(method)?;
+        let seed = self.draw_seed(seed);
+        let optimizer = method.optimizer(
+            self.model(), f64::INFINITY, &Annealing::default(), seed, &empirical, &fld, &self.opts);
+        Ok(self.run(optimizer, &empirical, &fld, iters))
+    }
+
+    // Levenberg–Marquardt on the parameters with nonzero var, e.g. after `anneal`;
+    // keeps the refined radicals and returns (sigma, normalized spectrum, radicals, converged)
+    #[pyo3(signature = (empirical, fld, tolerance=1e-8, max_iters=100))]
+    pub fn refine(
+        &mut self,
+        empirical: Vec<f64>,
+        fld: Vec<f64>,
+        tolerance: f64,
+        max_iters: usize,
+    ) -> PyResult<(f64, Vec<f64>, Vec<Radical>, bool)> {
+        if empirical.len() != fld.len() {
+            return Err(PyValueError::new_err("empirical and fld must have the same length"));
+        }
+
+        let rads: Vec<libesrafel::Radical> = self.rads.iter().map(rad_to_rs).collect();
+        let lm = LevMar { tolerance, max_iters };
+        let refined = libesrafel::fit::levenberg_marquardt(&empirical, &fld, &self.opts, rads, &lm);
+        self.rads = refined.rads.iter().map(rad_to_py).collect();
+        Ok((refined.sigma, refined.teor, self.rads.clone(), refined.converged))
+    }
+
+    // Nelder–Mead over the parameters with nonzero var, eqs included, until the simplex
+    // converges or `max_iters` iterations; keeps the best radicals and returns (sigma, normalized spectrum, radicals)
+    #[pyo3(signature = (empirical, fld, max_iters=1000, tolerance=1e-8))]
+    pub fn simplex(
+        &mut self,
+        empirical: Vec<f64>,
+        fld: Vec<f64>,
+        max_iters: usize,
+        tolerance: f64,
+    ) -> PyResult<(f64, Vec<f64>, Vec<Radical>)> {
+        if empirical.len() != fld.len() {
+            return Err(PyValueError::new_err("empirical and fld must have the same length"));
+        }
+
+        let rads: Vec<libesrafel::Radical> = self.rads.iter().map(rad_to_rs).collect();
+        let mut simplex = Simplex::new(&empirical, &fld, &self.opts, rads);
+        simplex.tolerance = tolerance;
+        Ok(self.run(Box::new(simplex), &empirical, &fld, max_iters))
+    }
+
+    // Standard errors and correlations of the parameters with nonzero var, at the current radicals:
+    // (names, values, errors, correlation matrix, strongly correlated pairs as (name, name, r)).
+    // Errors are infinite for parameters the spectrum doesn't determine, like the first amount when all vary.
+    pub fn uncertainties(
+        &self,
+        empirical: Vec<f64>,
+        fld: Vec<f64>,
+    ) -> PyResult<UncertaintyTuple> {
+        if empirical.len() != fld.len() {
+            return Err(PyValueError::new_err("empirical and fld must have the same length"));
+        }
+
+        let rads: Vec<libesrafel::Radical> = self.rads.iter().map(rad_to_rs).collect();
+        match libesrafel::fit::uncertainties(&empirical, &fld, &self.opts, &rads) {
+            Some(unc) => {
+                let flagged = unc.strongly_correlated().into_iter()
+                    .map(|(i, j, r)| (unc.names[i].clone(), unc.names[j].clone(), r))
+                    .collect();
+                Ok((unc.names, unc.values, unc.errors, unc.correlation, flagged))
+            }
+            None => Err(PyValueError::new_err("no varied parameters, or more parameters than points")),
+        }
+    }
+
+    // Seed of the last `fit` or `anneal`, to pass again and replay it
+    #[getter]
+    pub fn get_seed(&self) -> PyResult<Option<u64>> {
+        Ok(self.seed)
+    }
+
+    #[getter]
+    pub fn get_mwfq(&self) -> PyResult<Option<f64>> {
+        Ok(self.opts.mwfq)
+    }
+
+    #[setter]
+    pub fn set_mwfq(&mut self, value: Option<f64>) -> PyResult<()> {
+        self.opts.mwfq = value;
+        Ok(())
+    }
+
+    // Peak to peak modulation amplitude [G]; None or 0 for the plain output
+    #[getter]
+    pub fn get_modulation(&self) -> PyResult<Option<f64>> {
+        Ok(self.opts.modulation.as_ref().map(|m| m.amplitude))
+    }
+
+    #[setter]
+    pub fn set_modulation(&mut self, value: Option<f64>) -> PyResult<()> {
+        let harmonic = self.opts.modulation.as_ref().map_or(1, |m| m.harmonic);
+        self.opts.modulation = value.map(|amplitude| Modulation { amplitude, harmonic });
+        Ok(())
+    }
+
+    #[getter]
+    pub fn get_harmonic(&self) -> PyResult<u32> {
+        Ok(self.opts.modulation.as_ref().map_or(1, |m| m.harmonic))
+    }
+
+    #[setter]
+    pub fn set_harmonic(&mut self, value: u32) -> PyResult<()> {
+        match self.opts.modulation.as_mut() {
+            Some(m) => m.harmonic = value,
+            None => return Err(PyValueError::new_err("set a modulation amplitude first")),
+        }
+        Ok(())
+    }
+
+    // "absorption", "first-derivative" or "second-derivative"
+    #[getter]
+    pub fn get_output(&self) -> PyResult<String> {
+        Ok(output_to_str(self.opts.output).into())
+    }
+
+    #[setter]
+    pub fn set_output(&mut self, value: &str) -> PyResult<()> {
+        self.opts.output = output_from_str(value)?;
+        Ok(())
+    }
+
+    // "first", "second" or "breit-rabi"
+    #[getter]
+    pub fn get_positions(&self) -> PyResult<String> {
+        Ok(positions_to_str(self.opts.positions).into())
+    }
+
+    #[setter]
+    pub fn set_positions(&mut self, value: &str) -> PyResult<()> {
+        self.opts.positions = positions_from_str(value)?;
+        Ok(())
     }
 
     #[getter]
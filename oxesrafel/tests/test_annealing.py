@@ -0,0 +1,20 @@
+#!/usr/bin/env python3
+from oxesrafel import Radical, Param, Simulator
+
+def electron(lwa):
+    return Radical(lwa=lwa,
+                   lrtz=Param(100.0, 0.0),
+                   amount=Param(100.0, 0.0),
+                   dh1=Param(0.0, 0.0),
+                   nucs=[])
+
+fld = [3330.0 + 40.0 * i / 511 for i in range(512)]
+exp = Simulator(sweep=40.0, points=512.0, rads=[electron(Param(0.8, 0.0))]).calc_fld(fld)
+
+# Start far away, with room to move
+sim = Simulator(sweep=40.0, points=512.0, rads=[electron(Param(2.0, 0.1))])
+sigma, teor, rads = sim.anneal(exp, fld, 800, reheat_after=100)
+
+print(sigma, rads[0].lwa.val)
+if abs(rads[0].lwa.val - 0.8) < 0.05 and sim.rads[0].lwa.val == rads[0].lwa.val:
+    print("annealing: test passed.")
@@ -0,0 +1,17 @@
+#!/usr/bin/env python3
+from oxesrafel import Radical, Param, Simulator, fit_baseline
+
+rad = Radical(lwa=Param(1.0, 0.0),
+              lrtz=Param(50.0, 0.0),
+              amount=Param(100.0, 0.0),
+              dh1=Param(0.0, 0.0),
+              nucs=[])
+teor = Simulator(sweep=100.0, points=1024.0, rads=[rad]).calc()
+
+# Same spectrum, three times as intense, over an offset with a linear drift
+exp = [3.0 * y + 0.2 + 0.1 * (2.0 * i / 1023 - 1.0) for i, y in enumerate(teor)]
+norma, coeffs, values = fit_baseline(exp, teor, 1)
+
+print(norma, coeffs)
+if abs(norma - 3.0) < 1e-9 and abs(coeffs[0] - 0.2) < 1e-9 and abs(coeffs[1] - 0.1) < 1e-9:
+    print("baseline: test passed.")
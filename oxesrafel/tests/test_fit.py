@@ -0,0 +1,23 @@
+#!/usr/bin/env python3
+from oxesrafel import Radical, Param, Simulator
+
+def electron(lwa):
+    return Radical(lwa=lwa,
+                   lrtz=Param(100.0, 0.0),
+                   amount=Param(100.0, 0.0),
+                   dh1=Param(0.0, 0.0),
+                   nucs=[])
+
+fld = [3330.0 + 40.0 * i / 511 for i in range(512)]
+exp = Simulator(sweep=40.0, points=512.0, rads=[electron(Param(0.5, 0.0))]).calc_fld(fld)
+
+# Every method goes through the same dispatch of the library
+results = {}
+for method in ["mc", "annealing", "nelder-mead"]:
+    sim = Simulator(sweep=40.0, points=512.0, rads=[electron(Param(1.0, 0.1))])
+    sigma, teor, rads = sim.fit(exp, fld, 300, method=method)
+    results[method] = rads[0].lwa.val
+    print(method, sigma, rads[0].lwa.val)
+
+if all(abs(lwa - 0.5) < 0.05 for lwa in results.values()):
+    print("fit: test passed.")
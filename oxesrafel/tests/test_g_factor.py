@@ -0,0 +1,23 @@
+#!/usr/bin/env python3
+from oxesrafel import Radical, Param, Simulator
+
+# Two singlets at 9.5 GHz, g = 2.0023 and g = 2.0050
+rads = [Radical(lwa=Param(1.0, 0.0),
+                lrtz=Param(50.0, 0.0),
+                amount=Param(100.0, 0.0),
+                dh1=Param(0.0, 0.0),
+                nucs=[],
+                g=Param(g, 0.0)) for g in (2.0023, 2.0050)]
+
+sim = Simulator(sweep=100.0, points=1024.0, rads=rads, mwfq=9.5)
+
+fld = [3360.0 + 60.0 * i / 1023 for i in range(1024)]
+theor = sim.calc_fld(fld)
+
+# Derivative lines cross zero at the resonance fields
+crossings = [fld[i] for i in range(1, len(theor))
+             if theor[i - 1] > 0 >= theor[i]]
+expected = [714.4773 * 9.5 / g for g in (2.0050, 2.0023)]
+print(crossings, expected)
+if all(abs(c - e) < 0.2 for c, e in zip(crossings, expected)):
+    print("g-factor: test passed.")
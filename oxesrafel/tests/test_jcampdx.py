@@ -0,0 +1,17 @@
+#!/usr/bin/env python3
+
+from oxesrafel import ascii_import, jcampdx_import, jcampdx_export
+
+spectrum = "na-example-acn"
+
+with open("tests/data/{}.txt".format(spectrum)) as f:
+        idx, x_fld, y_int = ascii_import(f.read())
+
+        meta = {"title": spectrum, "mwfq": 9.5, "mod_amp": 0.5}
+        jdx = jcampdx_export(x_fld, y_int, meta)
+        print(jdx[:300])
+
+        idx2, x_fld2, y_int2, meta2 = jcampdx_import(jdx)
+        max_err = max(abs(a - b) for a, b in zip(y_int, y_int2))
+        if len(y_int2) == len(y_int) and max_err < 1e-3 * max(abs(y) for y in y_int) and meta2 == meta:
+            print("jcampdx round trip: test passed.")
@@ -0,0 +1,27 @@
+#!/usr/bin/env python3
+from oxesrafel import Radical, Param, Simulator
+
+# A singlet 2 G wide, modulated with 0.05 G and with 6 G
+rad = Radical(lwa=Param(2.0, 0.0),
+              lrtz=Param(50.0, 0.0),
+              amount=Param(100.0, 0.0),
+              dh1=Param(0.0, 0.0),
+              nucs=[])
+
+fld = [3320.0 + 0.04 * i for i in range(1001)]
+
+def peak_to_peak(theor):
+    top = max(range(len(theor)), key=lambda i: theor[i])
+    bottom = min(range(len(theor)), key=lambda i: theor[i])
+    return fld[bottom] - fld[top]
+
+sim = Simulator(sweep=40.0, points=1001.0, rads=[rad], positions="second")
+plain = peak_to_peak(sim.calc_fld(fld))
+sim.modulation = 0.05
+weak = peak_to_peak(sim.calc_fld(fld))
+sim.modulation = 6.0
+strong = peak_to_peak(sim.calc_fld(fld))
+
+print(plain, weak, strong)
+if abs(weak - plain) < 0.1 and strong > plain + 1.0:
+    print("modulation: test passed.")
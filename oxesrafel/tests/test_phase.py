@@ -0,0 +1,16 @@
+#!/usr/bin/env python3
+from oxesrafel import Radical, Param, Simulator
+
+# Half a turn of phase flips the spectrum
+rads = [Radical(lwa=Param(1.0, 0.0),
+                lrtz=Param(50.0, 0.0),
+                amount=Param(100.0, 0.0),
+                dh1=Param(0.0, 0.0),
+                nucs=[],
+                phase=Param(phase, 0.0)) for phase in (0.0, 180.0)]
+
+fld = [3340.0 + 0.02 * i for i in range(1001)]
+straight, flipped = [Simulator(sweep=20.0, points=1001.0, rads=[rad], positions="second").calc_fld(fld) for rad in rads]
+
+if all(abs(a + b) < 1e-9 for a, b in zip(straight, flipped)):
+    print("phase: test passed.")
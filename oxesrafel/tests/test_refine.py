@@ -0,0 +1,24 @@
+#!/usr/bin/env python3
+from oxesrafel import Radical, Nucleus, Param, Simulator
+
+def probe(lwa, lrtz, dh1, hpf):
+    return Radical(lwa=lwa,
+                   lrtz=lrtz,
+                   amount=Param(100.0, 0.0),
+                   dh1=dh1,
+                   nucs=[Nucleus(Param(1.0, 0.0), hpf, Param(1.0, 0.0))])
+
+# Second order positions: lines are simulated one by one, not snapped to the grid
+fld = [3310.0 + 80.0 * i / 1023 for i in range(1024)]
+target = probe(Param(0.8, 0.0), Param(60.0, 0.0), Param(0.3, 0.0), Param(14.5, 0.0))
+exp = Simulator(sweep=80.0, points=1024.0, rads=[target], positions="second").calc_fld(fld)
+
+# Close to the minimum, as after a Monte Carlo run
+start = probe(Param(0.85, 0.1), Param(65.0, 5.0), Param(0.25, 0.1), Param(14.45, 0.5))
+sim = Simulator(sweep=80.0, points=1024.0, rads=[start], positions="second")
+sigma, teor, rads, converged = sim.refine(exp, fld)
+
+rad = rads[0]
+print(sigma, converged, rad.lwa.val, rad.lrtz.val, rad.dh1.val)
+if converged and abs(rad.lwa.val - 0.8) < 1e-4 and abs(rad.dh1.val - 0.3) < 1e-4:
+    print("refine: test passed.")
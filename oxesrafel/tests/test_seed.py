@@ -0,0 +1,35 @@
+#!/usr/bin/env python3
+from oxesrafel import Radical, Param, Simulator, Rng
+
+def electron(lwa):
+    return Radical(lwa=lwa,
+                   lrtz=Param(100.0, 0.0),
+                   amount=Param(100.0, 0.0),
+                   dh1=Param(0.0, 0.0),
+                   nucs=[])
+
+# One generator for the whole run: same seed, same draws, but every param gets its own
+def draws(seed):
+    rng = Rng(seed)
+    pars = [Param(1.0, 0.5), Param(1.0, 0.5)]
+    for par in pars:
+        par.randomize(rng)
+    return [par.val for par in pars]
+
+fld = [3330.0 + 40.0 * i / 255 for i in range(256)]
+exp = Simulator(sweep=40.0, points=256.0, rads=[electron(Param(0.5, 0.0))]).calc_fld(fld)
+
+def run(method, seed):
+    sim = Simulator(sweep=40.0, points=256.0, rads=[electron(Param(1.0, 0.1))])
+    sigma, teor, rads = sim.fit(exp, fld, 50, method=method, seed=seed)
+    print(method, sim.seed, sigma, rads[0].lwa.val)
+    return sim.seed, (sigma, rads[0].lwa.val)
+
+# Same seed, same run; without a seed, the drawn one replays it
+replayed = all(run(method, 42) == run(method, 42) for method in ["mc", "annealing"])
+seed, first = run("mc", None)
+different = run("mc", 43)[1] != run("mc", 42)[1]
+
+if draws(7) == draws(7) and draws(7)[0] != draws(7)[1] and Rng().seed is not None \
+        and replayed and different and run("mc", seed) == (seed, first):
+    print("seed: test passed.")
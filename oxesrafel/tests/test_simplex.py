@@ -0,0 +1,23 @@
+#!/usr/bin/env python3
+from oxesrafel import Radical, Nucleus, Param, Simulator
+
+def radical(lwa, lrtz, nucs):
+    return Radical(lwa=lwa,
+                   lrtz=lrtz,
+                   amount=Param(100.0, 0.0),
+                   dh1=Param(0.0, 0.0),
+                   nucs=nucs)
+
+fld = [3340.0 + 40.0 * i / 1023 for i in range(1024)]
+target = radical(Param(0.6, 0.0), Param(100.0, 0.0), [Nucleus(Param(0.5, 0.0), Param(5.0, 0.0), Param(2.0, 0.0))])
+exp = Simulator(sweep=40.0, points=1024.0, rads=[target]).calc_fld(fld)
+
+# One proton instead of two, and lrtz against its upper bound
+start = radical(Param(0.8, 0.2), Param(100.0, 10.0), [Nucleus(Param(0.5, 0.0), Param(4.5, 1.0), Param(1.0, 1.0))])
+sim = Simulator(sweep=40.0, points=1024.0, rads=[start])
+sigma, teor, rads = sim.simplex(exp, fld)
+
+nuc = rads[0].nucs[0]
+print(sigma, rads[0].lwa.val, nuc.hpf.val, nuc.eqs.val)
+if nuc.eqs.val == 2.0 and abs(nuc.hpf.val - 5.0) < 0.05 and abs(rads[0].lwa.val - 0.6) < 0.01:
+    print("simplex: test passed.")
@@ -0,0 +1,18 @@
+#!/usr/bin/env python3
+from oxesrafel import Radical, Nucleus, Param, stick_spectrum
+
+# Two equivalent 14N: 1:2:3:2:1
+rad = Radical(lwa=Param(1.0, 0.0),
+              lrtz=Param(50.0, 0.0),
+              amount=Param(100.0, 0.0),
+              dh1=Param(0.0, 0.0),
+              nucs=[Nucleus(spin=Param(1.0, 0.0),
+                            hpf=Param(14.5, 0.0),
+                            eqs=Param(2.0, 0.0))])
+
+lines = stick_spectrum(rad)
+for field, intensity, m_i in lines:
+    print("{:8.2f} {:4.0f} {}".format(field, intensity, m_i))
+
+if [i for _, i, _ in lines] == [1.0, 2.0, 3.0, 2.0, 1.0]:
+    print("stick spectrum: test passed.")
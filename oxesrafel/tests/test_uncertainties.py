@@ -0,0 +1,25 @@
+#!/usr/bin/env python3
+import math
+from oxesrafel import Radical, Param, Simulator
+
+def singlet(lwa, dh1, amount):
+    return Radical(lwa=lwa,
+                   lrtz=Param(100.0, 0.0),
+                   amount=amount,
+                   dh1=dh1,
+                   nucs=[])
+
+# Two singlets almost on top of each other
+fld = [3310.0 + 80.0 * i / 1023 for i in range(1024)]
+rads = [singlet(Param(1.0, 0.1), Param(-0.05, 0.1), Param(100.0, 0.0)),
+        singlet(Param(1.1, 0.1), Param(0.05, 0.1), Param(100.0, 1.0))]
+sim = Simulator(sweep=80.0, points=1024.0, rads=rads, positions="second")
+clean = sim.calc_fld(fld)
+exp = [y + 1e-3 * math.sin(i * i * 0.7) for i, y in enumerate(clean)]
+
+names, values, errors, correlation, flagged = sim.uncertainties(exp, fld)
+print(names, errors)
+print(flagged)
+if (names[3] == "rad 2 amount" and all(0.0 < e < 10.0 for e in errors)
+        and any(a == "rad 2 lwa" and b == "rad 2 amount" for a, b, r in flagged)):
+    print("uncertainties: test passed.")
@@ -0,0 +1,18 @@
+#!/usr/bin/env python3
+from oxesrafel import Radical, Param, Simulator
+
+# Same singlet, Gaussian pseudo-Voigt and Voigt with no Lorentzian width
+rads = [Radical(lwa=Param(1.5, 0.0),
+                lrtz=Param(0.0, 0.0),
+                amount=Param(100.0, 0.0),
+                dh1=Param(0.0, 0.0),
+                nucs=[],
+                lineshape=shape) for shape in ("pseudo-voigt", "voigt")]
+
+fld = [3340.0 + 0.05 * i for i in range(2001)]
+gauss, voigt = [Simulator(sweep=100.0, points=2001.0, rads=[rad], positions="second").calc_fld(fld)
+                for rad in rads]
+
+top = max(gauss)
+if all(abs(a - b) < 1e-3 * top for a, b in zip(gauss, voigt)):
+    print("voigt: test passed.")